@@ -0,0 +1,124 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use slotmap::Key;
+
+use simple_gravity::error::SimError;
+use simple_gravity::simulation::Simulation;
+
+const USAGE: &str = "usage: simple_gravity-cli SCENE.rhai [--steps N] [--every K] [--output FILE]
+
+Runs a scene script without a window and writes every body's state as CSV.
+
+    --steps N        number of frames to simulate (default 1000)
+    --every K        only write every Kth frame (default 1)
+    --output FILE    write to FILE instead of stdout";
+
+fn parse_arg<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, SimError> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| SimError::Usage(format!("{} expects a number\n\n{}", flag, USAGE)))
+}
+
+fn with_path(e: std::io::Error, action: &str, path: &str) -> SimError {
+    SimError::Io(std::io::Error::new(
+        e.kind(),
+        format!("{} {}: {}", action, path, e),
+    ))
+}
+
+fn write_bodies(
+    out: &mut impl Write,
+    simulation: &mut Simulation,
+    step: usize,
+) -> Result<(), SimError> {
+    let time = simulation.time();
+    for (id, body) in simulation.bodies() {
+        let id = id
+            .map(|id| id.data().as_ffi().to_string())
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            step, time, id, body.pos.x, body.pos.y, body.vel.x, body.vel.y, body.mass, body.radius
+        )?;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        let code = match e {
+            SimError::Usage(_) => 2,
+            _ => 1,
+        };
+        std::process::exit(code);
+    }
+}
+
+fn run() -> Result<(), SimError> {
+    let mut args = std::env::args().skip(1);
+
+    let mut scene_path = None;
+    let mut steps = 1000;
+    let mut every = 1;
+    let mut output_path = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--steps" => steps = parse_arg("--steps", args.next())?,
+            "--every" => every = parse_arg::<usize>("--every", args.next())?.max(1),
+            "--output" => {
+                output_path = Some(args.next().ok_or_else(|| {
+                    SimError::Usage(format!("--output expects a path\n\n{}", USAGE))
+                })?)
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if scene_path.is_none() => scene_path = Some(arg),
+            _ => {
+                return Err(SimError::Usage(format!(
+                    "unexpected argument {}\n\n{}",
+                    arg, USAGE
+                )))
+            }
+        }
+    }
+
+    let scene_path = scene_path.ok_or_else(|| SimError::Usage(USAGE.to_string()))?;
+    let code = std::fs::read_to_string(&scene_path)
+        .map_err(|e| with_path(e, "couldn't read", &scene_path))?;
+
+    let mut out: Box<dyn Write> = match output_path {
+        Some(path) => {
+            let file = File::create(&path).map_err(|e| with_path(e, "couldn't create", &path))?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    };
+
+    let mut simulation = Simulation::default();
+    simulation.load_script(&code);
+    eprint!("{}", simulation.take_output());
+
+    writeln!(out, "step,time,id,x,y,vx,vy,mass,radius")?;
+    write_bodies(&mut out, &mut simulation, 0)?;
+
+    for step in 1..=steps {
+        simulation.step();
+        simulation.run_scripting();
+        eprint!("{}", simulation.take_output());
+
+        if step % every == 0 {
+            write_bodies(&mut out, &mut simulation, step)?;
+        }
+    }
+
+    out.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use glam::Vec2;
+
+// bodies covering more cells than this along either axis are checked against
+// everything instead of being added to every cell they touch
+const MAX_CELL_SPAN: i64 = 8;
+
+/// Uniform grid broadphase for finding overlapping circles
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    oversized: Vec<usize>,
+    bodies: Vec<(Vec2, f32)>,
+}
+
+impl SpatialHash {
+    /// Builds the grid from `(position, radius)` pairs, with cells sized from
+    /// the mean radius
+    pub fn new(bodies: Vec<(Vec2, f32)>) -> Self {
+        let mean_radius = if bodies.is_empty() {
+            1.0
+        } else {
+            bodies.iter().map(|(_, radius)| radius).sum::<f32>() / bodies.len() as f32
+        };
+
+        let mut spatial_hash = SpatialHash {
+            cell_size: (2.0 * mean_radius).max(1.0),
+            cells: HashMap::new(),
+            oversized: Vec::new(),
+            bodies,
+        };
+
+        for i in 0..spatial_hash.bodies.len() {
+            let (pos, radius) = spatial_hash.bodies[i];
+            let (min, max) = spatial_hash.cell_range(pos, radius);
+
+            if Self::is_oversized(min, max) {
+                spatial_hash.oversized.push(i);
+                continue;
+            }
+
+            for x in min.0..=max.0 {
+                for y in min.1..=max.1 {
+                    spatial_hash.cells.entry((x, y)).or_default().push(i);
+                }
+            }
+        }
+
+        spatial_hash
+    }
+
+    fn cell_range(&self, pos: Vec2, radius: f32) -> ((i64, i64), (i64, i64)) {
+        let min = ((pos - Vec2::splat(radius)) / self.cell_size).floor();
+        let max = ((pos + Vec2::splat(radius)) / self.cell_size).floor();
+
+        ((min.x as i64, min.y as i64), (max.x as i64, max.y as i64))
+    }
+
+    fn is_oversized(min: (i64, i64), max: (i64, i64)) -> bool {
+        max.0.saturating_sub(min.0) > MAX_CELL_SPAN || max.1.saturating_sub(min.1) > MAX_CELL_SPAN
+    }
+
+    /// Indices of bodies which might overlap the circle, sorted and without
+    /// duplicates
+    pub fn query(&self, pos: Vec2, radius: f32) -> Vec<usize> {
+        let (min, max) = self.cell_range(pos, radius);
+        if Self::is_oversized(min, max) {
+            return (0..self.bodies.len()).collect();
+        }
+
+        let mut found = self.oversized.clone();
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                if let Some(cell) = self.cells.get(&(x, y)) {
+                    found.extend(cell);
+                }
+            }
+        }
+
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+    /// Indices of bodies which overlap the circle
+    pub fn overlapping(&self, pos: Vec2, radius: f32) -> Vec<usize> {
+        self.query(pos, radius)
+            .into_iter()
+            .filter(|i| {
+                let (other_pos, other_radius) = self.bodies[*i];
+                (other_pos - pos).length_squared() <= (radius + other_radius).powi(2)
+            })
+            .collect()
+    }
+
+    /// Every pair `(i, j)` with `i < j` of bodies which might overlap
+    pub fn candidate_pairs(&self) -> Vec<(usize, usize)> {
+        let ordered = |a: usize, b: usize| if a < b { (a, b) } else { (b, a) };
+
+        let mut pairs = Vec::new();
+        for cell in self.cells.values() {
+            for (n, a) in cell.iter().enumerate() {
+                for b in &cell[n + 1..] {
+                    pairs.push(ordered(*a, *b));
+                }
+            }
+        }
+
+        for i in &self.oversized {
+            for j in (0..self.bodies.len()).filter(|j| j != i) {
+                pairs.push(ordered(*i, j));
+            }
+        }
+
+        pairs.sort_unstable();
+        pairs.dedup();
+        pairs
+    }
+}
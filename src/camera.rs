@@ -1,7 +1,7 @@
 use bevy_ecs::prelude::*;
 use egui_macroquad::{egui::Context, macroquad::prelude::*};
 
-use crate::{physics::KinematicBody, ui::input_state::MouseState};
+use crate::{physics::KinematicBody, selection::FollowBody, ui::input_state::MouseState};
 
 const SCREEN_WIDTH: f32 = 10_000.0;
 const SCREEN_HEIGHT: f32 = 10_000.0;
@@ -23,8 +23,6 @@ impl CameraRes {
     }
 }
 
-pub struct FollowBody(pub Option<Entity>);
-
 impl Default for CameraRes {
     fn default() -> Self {
         let display_rect = Rect::new(
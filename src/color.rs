@@ -0,0 +1,46 @@
+/// Color of a body, a script drawing or a graph. The simulation keeps its own
+/// type so that it doesn't need macroquad, which the app converts to when
+/// drawing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+pub const WHITE: Color = Color::new(1.0, 1.0, 1.0, 1.0);
+
+impl Color {
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Color { r, g, b, a }
+    }
+
+    pub const fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color::new(
+            r as f32 / 255.0,
+            g as f32 / 255.0,
+            b as f32 / 255.0,
+            a as f32 / 255.0,
+        )
+    }
+
+    /// Like `from_rgba`, with channels outside 0-255 clamped instead of wrapped
+    pub fn from_rgba_clamped(r: i64, g: i64, b: i64, a: i64) -> Self {
+        let channel = |c: i64| c.clamp(0, 255) as u8;
+        Color::from_rgba(channel(r), channel(g), channel(b), channel(a))
+    }
+}
+
+impl From<Color> for [u8; 4] {
+    fn from(color: Color) -> Self {
+        [color.r, color.g, color.b, color.a].map(|c| (c * 255.0) as u8)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<Color> for egui_macroquad::macroquad::color::Color {
+    fn from(color: Color) -> Self {
+        egui_macroquad::macroquad::color::Color::new(color.r, color.g, color.b, color.a)
+    }
+}
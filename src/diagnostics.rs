@@ -0,0 +1,167 @@
+use bevy_ecs::prelude::*;
+use glam::{DVec2, Vec2};
+
+use crate::{
+    physics::{pair_softening_sqr, BodySoftening, KinematicBody, Preview, SimTime, Softening, G},
+    scripting::{graphs::Graph, RhaiRes},
+};
+
+const ENERGY_WINDOW: &str = "Diagnostics: Energy";
+const MOMENTUM_WINDOW: &str = "Diagnostics: Momentum";
+
+// (label, window, colour)
+const GRAPHS: [(&str, &str, [u8; 3]); 5] = [
+    ("kinetic energy", ENERGY_WINDOW, [0, 0, 255]),
+    ("potential energy", ENERGY_WINDOW, [255, 0, 0]),
+    ("total energy", ENERGY_WINDOW, [0, 255, 0]),
+    ("momentum", MOMENTUM_WINDOW, [255, 255, 255]),
+    ("angular momentum", MOMENTUM_WINDOW, [255, 255, 0]),
+];
+
+/// Quantities which should be conserved, for checking how well the
+/// integrator is doing
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Diagnostics {
+    pub kinetic_energy: f32,
+    pub potential_energy: f32,
+    pub momentum: Vec2,
+    /// About the centre of mass
+    pub angular_momentum: f32,
+    pub center_of_mass: Vec2,
+}
+
+impl Diagnostics {
+    /// Takes each body with its softening length, so that the potential energy
+    /// matches the softened force used by the gravity solvers
+    pub fn compute(bodies: &[(KinematicBody, f32)], g: f32) -> Self {
+        // summed in f64, since drift is a small difference of large totals
+        let mut mass = 0.0;
+        let mut kinetic_energy = 0.0;
+        let mut momentum = DVec2::ZERO;
+        let mut weighted_pos = DVec2::ZERO;
+        for (body, _) in bodies {
+            let body_mass = body.mass as f64;
+            let vel = body.vel.as_dvec2();
+
+            mass += body_mass;
+            kinetic_energy += 0.5 * body_mass * vel.length_squared();
+            momentum += body_mass * vel;
+            weighted_pos += body_mass * body.pos.as_dvec2();
+        }
+
+        let (center_of_mass, com_vel) = if mass > 0.0 {
+            (weighted_pos / mass, momentum / mass)
+        } else {
+            (DVec2::ZERO, DVec2::ZERO)
+        };
+
+        let angular_momentum = bodies
+            .iter()
+            .map(|(body, _)| {
+                let r = body.pos.as_dvec2() - center_of_mass;
+                let v = body.vel.as_dvec2() - com_vel;
+                body.mass as f64 * r.perp_dot(v)
+            })
+            .sum::<f64>();
+
+        let mut potential_energy = 0.0;
+        for (i, (a, a_softening)) in bodies.iter().enumerate() {
+            for (b, b_softening) in &bodies[i + 1..] {
+                let softening_sqr =
+                    pair_softening_sqr(a_softening * a_softening, b_softening * b_softening);
+                let dist =
+                    ((b.pos - a.pos).as_dvec2().length_squared() + softening_sqr as f64).sqrt();
+                if dist > 0.0 {
+                    potential_energy -= g as f64 * a.mass as f64 * b.mass as f64 / dist;
+                }
+            }
+        }
+
+        Diagnostics {
+            kinetic_energy: kinetic_energy as f32,
+            potential_energy: potential_energy as f32,
+            momentum: momentum.as_vec2(),
+            angular_momentum: angular_momentum as f32,
+            center_of_mass: center_of_mass.as_vec2(),
+        }
+    }
+
+    pub fn total_energy(&self) -> f32 {
+        self.kinetic_energy + self.potential_energy
+    }
+}
+
+/// Change relative to the reference value, or `None` if the reference is too
+/// close to zero for that to mean anything
+pub fn relative_drift(value: f32, reference: f32) -> Option<f32> {
+    if reference.abs() > f32::EPSILON {
+        Some((value - reference) / reference.abs())
+    } else {
+        None
+    }
+}
+
+#[derive(Default)]
+pub struct DiagnosticsRes {
+    pub shown: bool,
+    pub graph: bool,
+    pub current: Option<Diagnostics>,
+    /// Drift is measured from here, set when diagnostics are first computed or reset
+    pub reference: Option<Diagnostics>,
+}
+
+pub fn diagnostics_sys(
+    mut diagnostics: ResMut<DiagnosticsRes>,
+    bodies: Query<(&KinematicBody, Option<&BodySoftening>), Without<Preview>>,
+    g: Res<G>,
+    softening: Res<Softening>,
+    sim_time: Res<SimTime>,
+    rhai: Res<RhaiRes>,
+) {
+    let mut graphs = rhai.graphs.write().unwrap();
+    if !diagnostics.graph {
+        for (label, _, _) in GRAPHS {
+            graphs.graphs.remove(label);
+        }
+    }
+
+    if !diagnostics.shown && !diagnostics.graph {
+        diagnostics.current = None;
+        return;
+    }
+
+    let bodies = bodies
+        .iter()
+        .map(|(body, body_softening)| {
+            (
+                body.clone(),
+                BodySoftening::resolve(body_softening, &softening),
+            )
+        })
+        .collect::<Vec<_>>();
+    let current = Diagnostics::compute(&bodies, g.0);
+
+    diagnostics.current = Some(current);
+    if diagnostics.reference.is_none() {
+        diagnostics.reference = Some(current);
+    }
+
+    if diagnostics.graph {
+        let values = [
+            current.kinetic_energy,
+            current.potential_energy,
+            current.total_energy(),
+            current.momentum.length(),
+            current.angular_momentum,
+        ];
+
+        for ((label, window, [r, g, b]), value) in GRAPHS.iter().zip(values) {
+            if !graphs.graphs.contains_key(*label) {
+                let mut graph = Graph::new(label, 1000, *r, *g, *b);
+                graph.window = window.to_string();
+                graphs.insert(graph);
+            }
+            graphs.add_xy_point(label, sim_time.0, value);
+        }
+    }
+}
@@ -4,12 +4,60 @@ use egui_macroquad::macroquad::prelude::*;
 use crate::{
     camera::CameraRes,
     force_lines::{DrawForceLines, ForceLine},
-    physics::KinematicBody,
-    ui::body_creation::{CreationData, CreationState},
+    orbit::OrbitalElements,
+    physics::{KinematicBody, Preview, G},
+    ui::body_creation::{CreationData, CreationMode, CreationState},
     ui::input_state::MouseState, scripting::RhaiRes,
+    selection::InspectedEntity,
 };
 
 const PREVIEW_COLOR: Color = Color::new(1.0, 1.0, 1.0, 0.75);
+const ORBIT_PREVIEW_COLOR: Color = Color::new(0.5, 0.7, 1.0, 0.6);
+const ORBIT_PREVIEW_SEGMENTS: usize = 128;
+
+/// Draws the orbit that a body at `pos` moving at `vel` would follow around
+/// `primary`, ignoring every other body
+fn draw_orbit_preview(primary: &KinematicBody, pos: Vec2, vel: Vec2, mu: f32, thickness: f32) {
+    let rel_pos = pos - primary.pos;
+    draw_line(
+        primary.pos.x,
+        primary.pos.y,
+        pos.x,
+        pos.y,
+        thickness / 2.0,
+        ORBIT_PREVIEW_COLOR,
+    );
+
+    let elements = match OrbitalElements::from_state(rel_pos, vel - primary.vel, mu) {
+        Some(elements) => elements,
+        None => return,
+    };
+
+    // hyperbolic orbits only reach out to their asymptotes
+    let max_anomaly = if elements.eccentricity < 1.0 {
+        std::f32::consts::PI
+    } else {
+        (-1.0 / elements.eccentricity).acos() * 0.95
+    };
+
+    let points = (0..=ORBIT_PREVIEW_SEGMENTS)
+        .filter_map(|i| {
+            let t = i as f32 / ORBIT_PREVIEW_SEGMENTS as f32;
+            let true_anomaly = -max_anomaly + 2.0 * max_anomaly * t;
+            let (pos, _) = OrbitalElements {
+                true_anomaly,
+                ..elements
+            }
+            .to_state(mu)?;
+            Some(primary.pos + pos)
+        })
+        .collect::<Vec<_>>();
+
+    for segment in points.windows(2) {
+        let (p1, p2) = (segment[0], segment[1]);
+        draw_line(p1.x, p1.y, p2.x, p2.y, thickness, ORBIT_PREVIEW_COLOR);
+    }
+}
 
 pub fn calculate_sides(radius: f32, camera_res: &CameraRes) -> u8 {
     let camera_view_size = (camera_res.camera.screen_to_world(camera_res.screen_size)
@@ -21,12 +69,15 @@ pub fn calculate_sides(radius: f32, camera_res: &CameraRes) -> u8 {
     sides as u8
 }
 
-pub fn draw_bodies_sys(query: Query<(&KinematicBody, Option<&Color>)>, camera_res: Res<CameraRes>) {
+pub fn draw_bodies_sys(
+    query: Query<(&KinematicBody, Option<&crate::color::Color>)>,
+    camera_res: Res<CameraRes>,
+) {
     for (body, color) in query.iter() {
-        let color = color.unwrap_or(&WHITE);
+        let color = color.map_or(WHITE, |color| (*color).into());
 
         let sides = calculate_sides(body.radius, &camera_res);
-        draw_poly(body.pos.x, body.pos.y, sides, body.radius, 0.0, *color);
+        draw_poly(body.pos.x, body.pos.y, sides, body.radius, 0.0, color);
     }
 }
 
@@ -35,7 +86,22 @@ pub fn draw_create_preview(
     creation_data: Res<CreationData>,
     camera_res: Res<CameraRes>,
     mouse_state: Res<MouseState>,
+    bodies: Query<(Entity, &KinematicBody), Without<Preview>>,
+    inspected_entity: Res<InspectedEntity>,
+    g: Res<G>,
 ) {
+    let line_thickness = (creation_data.radius / 2.0).max(10.0);
+
+    // in orbit modes the velocity doesn't depend on the drag, so the orbit is
+    // shown before clicking too
+    let draw_orbit = |pos: Vec2| {
+        if let Some(primary) = creation_data.primary(pos, &bodies, &inspected_entity) {
+            let vel = creation_data.velocity(pos, pos, Some(primary), g.0);
+            let mu = g.0 * (primary.mass + creation_data.mass);
+            draw_orbit_preview(primary, pos, vel, mu, line_thickness / 2.0);
+        }
+    };
+
     let draw_body_preview = |point: &Vec2| {
         let sides = calculate_sides(creation_data.radius, &camera_res);
         draw_poly(
@@ -51,18 +117,23 @@ pub fn draw_create_preview(
     match *creation_state {
         CreationState::Initiated => {
             draw_body_preview(&mouse_state.prev_position);
+            draw_orbit(mouse_state.prev_position);
         }
         CreationState::Clicked { start_point } => {
             draw_body_preview(&start_point);
 
-            draw_line(
-                start_point.x,
-                start_point.y,
-                mouse_state.prev_position.x,
-                mouse_state.prev_position.y,
-                (creation_data.radius / 2.0).max(10.0),
-                WHITE,
-            );
+            if creation_data.mode == CreationMode::Drag {
+                draw_line(
+                    start_point.x,
+                    start_point.y,
+                    mouse_state.prev_position.x,
+                    mouse_state.prev_position.y,
+                    line_thickness,
+                    WHITE,
+                );
+            } else {
+                draw_orbit(start_point);
+            }
         }
         _ => {}
     }
@@ -90,13 +161,16 @@ pub fn draw_force_lines(
     }
 }
 
-pub fn draw_rhai_stuff(mut rhai: ResMut<RhaiRes>) {
-    let mut draw_fn = rhai.drawings.clone();
-    while let crate::scripting::DrawFn::Draw(f) = draw_fn {
-        draw_fn = f();
-    }
+pub fn draw_rhai_stuff(rhai: Res<RhaiRes>, camera_res: Res<CameraRes>) {
+    let layer_drawings = rhai
+        .layers
+        .values()
+        .filter(|layer| layer.visible)
+        .flat_map(|layer| layer.drawings.iter());
 
-    rhai.drawings = crate::scripting::DrawFn::Finished;
+    for drawing in layer_drawings.chain(rhai.drawings.iter()) {
+        drawing.draw(&camera_res.camera);
+    }
 }
 
 
@@ -1,15 +1,37 @@
 use std::error::Error;
 
 #[derive(Debug)]
-pub enum SimError {}
+pub enum SimError {
+    Io(std::io::Error),
+    Usage(String),
+    Scene(String),
+    Json(serde_json::Error),
+}
 
 impl std::fmt::Display for SimError {
     fn fmt(
         &self,
         formatter: &mut std::fmt::Formatter<'_>,
     ) -> std::result::Result<(), std::fmt::Error> {
-        write!(formatter, "SimError")
+        match self {
+            SimError::Io(e) => write!(formatter, "{}", e),
+            SimError::Usage(msg) => write!(formatter, "{}", msg),
+            SimError::Scene(msg) => write!(formatter, "{}", msg),
+            SimError::Json(e) => write!(formatter, "invalid scene file: {}", e),
+        }
     }
 }
 
 impl Error for SimError {}
+
+impl From<std::io::Error> for SimError {
+    fn from(e: std::io::Error) -> Self {
+        SimError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SimError {
+    fn from(e: serde_json::Error) -> Self {
+        SimError::Json(e)
+    }
+}
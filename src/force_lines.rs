@@ -1,7 +1,9 @@
 use bevy_ecs::prelude::*;
-use egui_macroquad::macroquad::prelude::*;
+use glam::Vec2;
 
-use crate::physics::{KinematicBody, Preview};
+use crate::physics::{
+    gravity_accel, pair_softening_sqr, BodySoftening, KinematicBody, Preview, Softening,
+};
 
 pub struct DrawForceLines(pub bool);
 
@@ -13,22 +15,43 @@ pub struct ForceLine {
 
 pub fn force_line_sys(
     mut force_line_query: Query<
-        (&KinematicBody, Option<&mut Vec<ForceLine>>, Entity),
+        (
+            &KinematicBody,
+            Option<&mut Vec<ForceLine>>,
+            Option<&BodySoftening>,
+            Entity,
+        ),
         Without<Preview>,
     >,
-    body_query: Query<(&KinematicBody, Entity), Without<Preview>>,
+    body_query: Query<(&KinematicBody, Option<&BodySoftening>, Entity), Without<Preview>>,
     draw_force_lines: Res<DrawForceLines>,
+    softening: Res<Softening>,
     mut commands: Commands,
 ) {
     if draw_force_lines.0 {
-        for (body, force_lines, entity) in force_line_query.iter_mut() {
+        for (body, force_lines, body_softening, entity) in force_line_query.iter_mut() {
             if let Some(mut force_lines) = force_lines {
                 force_lines.clear();
-                for (other_body, _) in body_query.iter().filter(|(_, e)| *e != entity) {
-                    let radius_sqr = (other_body.pos - body.pos).length_squared();
+                let body_softening = BodySoftening::resolve(body_softening, &softening);
+                for (other_body, other_softening, _) in
+                    body_query.iter().filter(|(_, _, e)| *e != entity)
+                {
+                    let other_softening = BodySoftening::resolve(other_softening, &softening);
+                    let softening_sqr = pair_softening_sqr(
+                        body_softening * body_softening,
+                        other_softening * other_softening,
+                    );
+                    // G is left out, the lines only compare pulls with each other
+                    let accel = gravity_accel(
+                        body.pos,
+                        other_body.pos,
+                        other_body.mass,
+                        1.0,
+                        softening_sqr,
+                    );
 
                     force_lines.push(ForceLine {
-                        magnitude: other_body.mass / radius_sqr,
+                        magnitude: accel.length(),
                         end_point: other_body.pos,
                         max_width: body.radius.min(other_body.radius) * 0.5,
                     });
@@ -0,0 +1,328 @@
+use std::collections::VecDeque;
+
+use bevy_ecs::prelude::*;
+use slotmap::DefaultKey;
+
+use crate::{
+    color::Color,
+    physics::{
+        AdaptiveDT, BodyRestitution, BodySoftening, CollisionMode, Fragmentation, GravitySolver,
+        Integrator, KinematicBody, Paused, PhysicsToggles, Preview, Restitution, SimTime,
+        Softening, Steps, DT, G,
+    },
+    scripting::{RhaiBody, RhaiID, RhaiRes, ScriptSource},
+    selection::{FollowBody, InspectedEntity},
+    trails::{RelativeTrails, Trail},
+};
+
+struct BodySnapshot {
+    body: KinematicBody,
+    id: Option<DefaultKey>,
+    registered: bool,
+    color: Option<Color>,
+    trail: Option<Trail>,
+    softening: Option<BodySoftening>,
+    restitution: Option<BodyRestitution>,
+}
+
+/// The global settings which change how the simulation runs
+struct Globals {
+    g: G,
+    dt: DT,
+    time: SimTime,
+    steps: Steps,
+    softening: Softening,
+    restitution: Restitution,
+    toggles: PhysicsToggles,
+    collision_mode: CollisionMode,
+    fragmentation: Fragmentation,
+    integrator: Integrator,
+    gravity_solver: GravitySolver,
+    adaptive_dt: AdaptiveDT,
+}
+
+impl Globals {
+    fn capture(world: &World) -> Self {
+        fn get<T: Copy + Send + Sync + 'static>(world: &World) -> T {
+            *world.get_resource::<T>().unwrap()
+        }
+
+        Globals {
+            g: get(world),
+            dt: get(world),
+            time: get(world),
+            steps: get(world),
+            softening: get(world),
+            restitution: get(world),
+            toggles: get(world),
+            collision_mode: get(world),
+            fragmentation: get(world),
+            integrator: get(world),
+            gravity_solver: get(world),
+            adaptive_dt: get(world),
+        }
+    }
+
+    fn restore(&self, world: &mut World) {
+        world.insert_resource(self.g);
+        world.insert_resource(self.dt);
+        world.insert_resource(self.time);
+        world.insert_resource(self.steps);
+        world.insert_resource(self.softening);
+        world.insert_resource(self.restitution);
+        world.insert_resource(self.toggles);
+        world.insert_resource(self.collision_mode);
+        world.insert_resource(self.fragmentation);
+        world.insert_resource(self.integrator);
+        world.insert_resource(self.gravity_solver);
+        world.insert_resource(self.adaptive_dt);
+    }
+}
+
+pub struct Snapshot {
+    bodies: Vec<BodySnapshot>,
+    globals: Globals,
+}
+
+impl Snapshot {
+    pub fn capture(world: &mut World) -> Self {
+        let bodies = world
+            .query_filtered::<(
+                &KinematicBody,
+                Option<&RhaiID>,
+                Option<&RhaiBody>,
+                Option<&Color>,
+                Option<&Trail>,
+                Option<&BodySoftening>,
+                Option<&BodyRestitution>,
+            ), Without<Preview>>()
+            .iter(world)
+            .map(
+                |(body, id, registered, color, trail, softening, restitution)| BodySnapshot {
+                    body: body.clone(),
+                    id: id.map(|id| id.0),
+                    registered: registered.is_some(),
+                    color: color.copied(),
+                    trail: trail.cloned(),
+                    softening: softening.copied(),
+                    restitution: restitution.copied(),
+                },
+            )
+            .collect();
+
+        Snapshot {
+            bodies,
+            globals: Globals::capture(world),
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.globals.time.0
+    }
+
+    /// Puts every body and setting back. A script with an `update`, hooks or
+    /// buttons can't be rewound, so it's run again from the start instead,
+    /// which is reported by returning true.
+    pub fn restore(&self, world: &mut World) -> bool {
+        // entities change when bodies are respawned, so the bodies the UI
+        // points at are found again by id
+        let id_of = |world: &World, entity: Option<Entity>| {
+            entity
+                .and_then(|entity| world.get::<RhaiID>(entity))
+                .map(|id| id.0)
+        };
+        let inspected = id_of(world, world.get_resource::<InspectedEntity>().unwrap().0);
+        let relative = id_of(world, world.get_resource::<RelativeTrails>().unwrap().0);
+        let followed = id_of(
+            world,
+            world.get_resource::<FollowBody>().and_then(|follow| follow.0),
+        );
+
+        let entities = world
+            .query_filtered::<Entity, (With<KinematicBody>, Without<Preview>)>()
+            .iter(world)
+            .collect::<Vec<_>>();
+        for entity in entities {
+            world.despawn(entity);
+        }
+
+        let mut rhai = world.remove_resource::<RhaiRes>().unwrap();
+        let restarted = rhai.has_callbacks();
+        if restarted {
+            let code = world
+                .get_resource::<ScriptSource>()
+                .map(|source| source.code.read().unwrap().clone())
+                .unwrap_or_default();
+            rhai = rhai.restarted(&code);
+        }
+        rhai.existing_bodies.write().unwrap().clear();
+        rhai.newly_added_bodies.write().unwrap().clear();
+        rhai.commands.write().unwrap().clear();
+        rhai.events.write().unwrap().clear();
+
+        let mut entity_of = std::collections::BTreeMap::new();
+        let mut unregistered = Vec::new();
+        for snapshot in &self.bodies {
+            let mut builder = world.spawn();
+            builder.insert(snapshot.body.clone());
+
+            if snapshot.registered {
+                builder.insert(RhaiBody);
+            }
+            if let Some(color) = snapshot.color {
+                builder.insert(color);
+            }
+            if let Some(trail) = &snapshot.trail {
+                builder.insert(trail.clone());
+            }
+            if let Some(softening) = snapshot.softening {
+                builder.insert(softening);
+            }
+            if let Some(restitution) = snapshot.restitution {
+                builder.insert(restitution);
+            }
+
+            let entity = builder.id();
+            match snapshot.id {
+                Some(key) => {
+                    rhai.existing_bodies.write().unwrap().insert(key, entity);
+                    world.entity_mut(entity).insert(RhaiID(key));
+                    entity_of.insert(key, entity);
+                }
+                None => unregistered.push(entity),
+            }
+        }
+        // after every saved id is taken, so that new ids can't clash with them
+        for entity in unregistered {
+            let key = rhai.register_body(entity);
+            world.entity_mut(entity).insert(RhaiID(key));
+        }
+
+        let find = |id: Option<DefaultKey>| id.and_then(|id| entity_of.get(&id).copied());
+        world.insert_resource(InspectedEntity(find(inspected)));
+        world.insert_resource(RelativeTrails(find(relative)));
+        if world.get_resource::<FollowBody>().is_some() {
+            world.insert_resource(FollowBody(find(followed)));
+        }
+
+        if restarted {
+            rhai.output.write().unwrap().push_str(&format!(
+                "Rewound to t = {:.2}. The script was restarted from the beginning, so its variables were reset.\n",
+                self.time()
+            ));
+            if let Some(mut source) = world.get_resource_mut::<ScriptSource>() {
+                source.output = Some(rhai.output.clone());
+            }
+        }
+        world.insert_resource(rhai);
+        self.globals.restore(world);
+
+        restarted
+    }
+}
+
+/// Snapshots taken every few steps, so that the simulation can be rewound.
+/// Off by default since every snapshot copies every body and its trail.
+pub struct History {
+    pub enabled: bool,
+    pub snapshots: VecDeque<Snapshot>,
+    /// The oldest snapshots are dropped past this
+    pub capacity: usize,
+    /// Physics steps between snapshots
+    pub interval: usize,
+    steps_since_snapshot: usize,
+    /// The snapshot being looked at, or `None` while running normally
+    pub cursor: Option<usize>,
+    /// A snapshot that the UI asked to go to
+    pub pending: Option<usize>,
+    /// Set when going back restarted the script, so the UI can say so
+    pub script_restarted: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            snapshots: VecDeque::new(),
+            capacity: 200,
+            interval: 10,
+            steps_since_snapshot: 0,
+            cursor: None,
+            pending: None,
+            script_restarted: false,
+        }
+    }
+}
+
+impl History {
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.steps_since_snapshot = 0;
+        self.cursor = None;
+        self.pending = None;
+        self.script_restarted = false;
+    }
+
+    fn push(&mut self, snapshot: Snapshot) {
+        self.snapshots.push_back(snapshot);
+        while self.snapshots.len() > self.capacity.max(1) {
+            self.snapshots.pop_front();
+            self.cursor = self.cursor.map(|cursor| cursor.saturating_sub(1));
+        }
+    }
+}
+
+fn go_to_snapshot(world: &mut World, index: usize) {
+    let mut history = world.remove_resource::<History>().unwrap();
+
+    // keep the current state so that it can be returned to
+    let mut index = index;
+    if history.cursor.is_none() {
+        let len = history.snapshots.len();
+        history.push(Snapshot::capture(world));
+        // a full timeline drops its oldest snapshot, which shifts the rest
+        // down, and asking for the dropped one gets the new oldest instead
+        index = index.saturating_sub(len + 1 - history.snapshots.len());
+    }
+
+    if let Some(snapshot) = history.snapshots.get(index) {
+        history.script_restarted |= snapshot.restore(world);
+        history.cursor = Some(index);
+        world.insert_resource(Paused(true));
+    }
+
+    world.insert_resource(history);
+}
+
+pub fn history_sys(world: &mut World) {
+    let pending = world.get_resource_mut::<History>().unwrap().pending.take();
+    if let Some(index) = pending {
+        go_to_snapshot(world, index);
+        return;
+    }
+
+    let paused = world.get_resource::<Paused>().unwrap().0;
+    let steps = world.get_resource::<Steps>().unwrap().0;
+
+    let mut history = world.get_resource_mut::<History>().unwrap();
+    if !history.enabled || paused {
+        return;
+    }
+
+    // unpausing while looking at an old snapshot continues from it, and the
+    // snapshots after it no longer happened
+    if let Some(cursor) = history.cursor.take() {
+        history.snapshots.truncate(cursor + 1);
+        history.steps_since_snapshot = 0;
+        history.script_restarted = false;
+    }
+
+    history.steps_since_snapshot += steps.max(1);
+    if history.steps_since_snapshot < history.interval && !history.snapshots.is_empty() {
+        return;
+    }
+    history.steps_since_snapshot = 0;
+
+    let snapshot = Snapshot::capture(world);
+    world.get_resource_mut::<History>().unwrap().push(snapshot);
+}
@@ -0,0 +1,30 @@
+// bevy
+#![allow(clippy::too_many_arguments)]
+#![allow(clippy::type_complexity)]
+
+pub mod error;
+
+pub mod broadphase;
+pub mod color;
+pub mod diagnostics;
+pub mod force_lines;
+pub mod history;
+pub mod orbit;
+pub mod physics;
+pub mod preview;
+pub mod quadtree;
+pub mod scene;
+pub mod scripting;
+pub mod selection;
+pub mod simulation;
+pub mod trails;
+
+// the app itself, which needs a window
+#[cfg(feature = "gui")]
+pub mod camera;
+#[cfg(feature = "gui")]
+pub mod draw;
+#[cfg(feature = "gui")]
+pub mod main_state;
+#[cfg(feature = "gui")]
+pub mod ui;
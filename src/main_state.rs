@@ -2,17 +2,16 @@ use bevy_ecs::prelude::*;
 use egui_macroquad::egui::FontFamily;
 use egui_macroquad::macroquad::prelude::*;
 
-use crate::physics::{self, Paused};
+use crate::physics::Paused;
 
-use crate::scripting::RhaiRes;
+use crate::simulation::Simulation;
 use crate::ui::body_creation::{CreationData, CreationState};
+use crate::scripting::ScriptSource;
 use crate::ui::code_editor::CodeEditor;
 use crate::ui::input_state::MouseState;
-use crate::ui::inspect::InspectedEntity;
 
 pub struct MainState {
-    pub world: World,
-    main_physics_schedule: Schedule,
+    pub simulation: Simulation,
     preview_physics_schedule: Schedule,
     input_schedule: Schedule,
     draw_schedule: Schedule,
@@ -20,63 +19,28 @@ pub struct MainState {
 
 impl Default for MainState {
     fn default() -> Self {
-        let mut world = {
-            let mut world = World::new();
+        let mut simulation = Simulation::default();
 
-            world.insert_resource(crate::physics::DT(1.0));
-            world.insert_resource(crate::physics::G(100.0));
+        {
+            let world = &mut simulation.world;
 
             let camera_res = crate::camera::CameraRes::default();
             set_camera(&camera_res.camera);
             world.insert_resource(camera_res);
-            world.insert_resource(crate::camera::FollowBody(None));
-            world.insert_resource(crate::trails::RelativeTrails(None));
+            world.insert_resource(crate::selection::FollowBody(None));
+            world.insert_resource(crate::ui::inspect::OrbitPrimary(None));
+            world.insert_resource(CodeEditor::default());
 
             let mouse_state_res = MouseState::default();
             world.insert_resource(mouse_state_res);
 
             world.insert_resource(CreationData::default());
             world.insert_resource(CreationState::Unstarted);
-            world.insert_resource(InspectedEntity(None));
             world.insert_resource(egui_macroquad::egui::Context::default());
 
-            world.insert_resource(Paused(false));
-            world.insert_resource(physics::PhysicsToggles { collisions: true, integration: true });
-
             world.insert_resource(crate::preview::PreviewTrailTick::default());
             world.insert_resource(crate::preview::MultiPreview(false));
-
-            world.insert_resource(crate::force_lines::DrawForceLines(false));
-            world.insert_resource(crate::trails::DrawTrails(true));
-
-            world.insert_resource(crate::ui::code_editor::CodeEditor::default());
-
-            world.insert_resource(RhaiRes::default());
-
-            world
-        };
-
-        let main_physics_schedule = {
-            let mut main_physics_schedule = Schedule::default();
-
-            main_physics_schedule.add_stage(
-                "physics",
-                SystemStage::single_threaded()
-                    .with_system(crate::physics::gravity_sys.system().label("gravity"))
-                    .with_system(
-                        crate::physics::collision_sys
-                            .system()
-                            .label("collision")
-                            .after("gravity"),
-                    )
-                    .with_system(crate::physics::integration_sys.system().after("collision"))
-                    .with_system(crate::trails::trail_sys.system())
-                    .with_system(crate::trails::clear_trails_sys.system())
-                    .with_system(crate::force_lines::force_line_sys.system()),
-            );
-
-            main_physics_schedule
-        };
+        }
 
         let preview_physics_schedule = {
             let mut preview_physics_schedule = Schedule::default();
@@ -85,20 +49,14 @@ impl Default for MainState {
                 "preview",
                 SystemStage::single_threaded()
                     .with_system(
-                        crate::preview::preview_gravity_sys
+                        crate::preview::preview_prediction_sys
                             .system()
-                            .label("gravity"),
-                    )
-                    .with_system(
-                        crate::physics::preview_integration_sys
-                            .system()
-                            .label("integration")
-                            .after("gravity"),
+                            .label("prediction"),
                     )
                     .with_system(
                         crate::trails::preview_trail_sys
                             .system()
-                            .after("integration"),
+                            .after("prediction"),
                     ),
             );
 
@@ -113,6 +71,12 @@ impl Default for MainState {
                 SystemStage::single_threaded()
                     .with_system(crate::draw::draw_bodies_sys.system().label("bodies"))
                     .with_system(crate::draw::draw_create_preview.system())
+                    .with_system(crate::preview::draw_preview_impacts_sys.system())
+                    .with_system(
+                        crate::preview::draw_trajectory_predictions_sys
+                            .system()
+                            .before("bodies"),
+                    )
                     .with_system(crate::trails::draw_trail_sys.system().before("bodies"))
                     .with_system(crate::draw::draw_force_lines.system().before("bodies"))
                     .with_system(crate::camera::update_camera_sys.system())
@@ -142,6 +106,16 @@ impl Default for MainState {
                         crate::ui::code_editor::code_editor_sys
                             .system()
                             .after("top_panel"),
+                    )
+                    .with_system(
+                        crate::ui::script_controls::script_controls_sys
+                            .system()
+                            .after("top_panel"),
+                    )
+                    .with_system(
+                        crate::ui::diagnostics::diagnostics_panel_sys
+                            .system()
+                            .after("top_panel"),
                     ),
             );
 
@@ -153,7 +127,7 @@ impl Default for MainState {
             draw_schedule
         };
 
-        let mut input_schedule = {
+        let input_schedule = {
             let mut input_schedule = Schedule::default();
 
             input_schedule.add_stage(
@@ -175,44 +149,22 @@ impl Default for MainState {
                             .after("inspect")
                             .before("update_mouse"),
                     )
-                    .with_system(crate::ui::handle_keybinds_sys.system()),
-            );
-
-            input_schedule.add_stage(
-                "scripting",
-                SystemStage::single_threaded()
-                    .with_system(crate::scripting::run_code_sys.system().label("run"))
                     .with_system(
-                        crate::scripting::run_rhai_commands_sys
+                        crate::ui::input_state::script_click_sys
                             .system()
-                            .label("commands")
-                            .after("run"),
+                            .after("update_mouse"),
                     )
-                    .with_system(
-                        crate::scripting::run_script_update_sys
-                            .system()
-                            .label("update")
-                            .after("commands"),
-                    ),
+                    .with_system(crate::ui::handle_keybinds_sys.system()),
             );
 
             input_schedule
         };
 
-        {
-            use std::sync::{Arc, RwLock};
-            world.insert_resource(CodeEditor {
-                code: Arc::new(RwLock::new(include_str!("../rhai_scripts/basic_orbit.rhai").to_string())),
-                should_run: true,
-                ..CodeEditor::default()
-            });
-            input_schedule.run(&mut world);
-            world.insert_resource(CodeEditor::default());
-        }
+        simulation.load_script(include_str!("../rhai_scripts/basic_orbit.rhai"));
+        simulation.world.insert_resource(ScriptSource::default());
 
         Self {
-            world,
-            main_physics_schedule,
+            simulation,
             preview_physics_schedule,
             input_schedule,
             draw_schedule,
@@ -222,15 +174,15 @@ impl Default for MainState {
 
 impl MainState {
     pub fn update(&mut self) -> Result<(), crate::error::SimError> {
-        self.main_physics_schedule.run(&mut self.world);
+        self.simulation.step();
 
-        let was_paused = self.world.get_resource::<Paused>().unwrap().0;
-        self.world.insert_resource(Paused(false));
+        let was_paused = self.simulation.world.get_resource::<Paused>().unwrap().0;
+        self.simulation.world.insert_resource(Paused(false));
         let start_time = get_time();
         while get_time() - start_time < 0.0075 {
-            self.preview_physics_schedule.run(&mut self.world);
+            self.preview_physics_schedule.run(&mut self.simulation.world);
         }
-        self.world.insert_resource(Paused(was_paused));
+        self.simulation.world.insert_resource(Paused(was_paused));
 
         clear_background(BLACK);
 
@@ -250,12 +202,13 @@ impl MainState {
             );
             egui_ctx.set_style(style);
 
-            self.world.insert_resource(egui_ctx.clone());
-            self.draw_schedule.run(&mut self.world);
+            self.simulation.world.insert_resource(egui_ctx.clone());
+            self.draw_schedule.run(&mut self.simulation.world);
         });
         egui_macroquad::draw();
 
-        self.input_schedule.run(&mut self.world);
+        self.input_schedule.run(&mut self.simulation.world);
+        self.simulation.run_scripting();
 
         Ok(())
     }
@@ -0,0 +1,202 @@
+use std::f64::consts::TAU;
+
+use bevy_ecs::prelude::*;
+use glam::{DVec2, Vec2};
+
+use crate::physics::KinematicBody;
+
+/// Keplerian elements of a two body orbit in the plane, relative to a primary.
+/// Angles are in radians, measured counterclockwise from the x axis.
+#[derive(Clone, Copy, Debug)]
+pub struct OrbitalElements {
+    /// Negative for hyperbolic orbits
+    pub semi_major_axis: f32,
+    pub eccentricity: f32,
+    pub argument_of_periapsis: f32,
+    /// Measured in the direction of motion
+    pub true_anomaly: f32,
+    /// Whether the body goes around clockwise
+    pub clockwise: bool,
+}
+
+impl OrbitalElements {
+    /// The elements of an orbit with the given position and velocity relative
+    /// to the primary, or `None` if the body is on top of the primary or moving
+    /// straight towards or away from it
+    pub fn from_state(pos: Vec2, vel: Vec2, mu: f32) -> Option<Self> {
+        let (r, v, mu) = (pos.as_dvec2(), vel.as_dvec2(), mu as f64);
+        let dist = r.length();
+        let h = r.perp_dot(v);
+        if dist <= 0.0 || mu <= 0.0 || h == 0.0 {
+            return None;
+        }
+
+        let e_vec = ((v.length_squared() - mu / dist) * r - r.dot(v) * v) / mu;
+        let eccentricity = e_vec.length();
+        let energy = 0.5 * v.length_squared() - mu / dist;
+
+        // circular orbits have no periapsis, so measure from the x axis
+        let periapsis_dir = if eccentricity > 1e-6 {
+            e_vec / eccentricity
+        } else {
+            DVec2::X
+        };
+        let sign = h.signum();
+        let true_anomaly = sign * periapsis_dir.perp_dot(r).atan2(periapsis_dir.dot(r));
+
+        Some(OrbitalElements {
+            semi_major_axis: (-mu / (2.0 * energy)) as f32,
+            eccentricity: eccentricity as f32,
+            argument_of_periapsis: periapsis_dir.y.atan2(periapsis_dir.x) as f32,
+            true_anomaly: true_anomaly as f32,
+            clockwise: h < 0.0,
+        })
+    }
+
+    /// Position and velocity relative to the primary, or `None` if the
+    /// elements don't describe an orbit which passes through the true anomaly
+    pub fn to_state(&self, mu: f32) -> Option<(Vec2, Vec2)> {
+        let (a, e, mu) = (
+            self.semi_major_axis as f64,
+            self.eccentricity as f64,
+            mu as f64,
+        );
+        let nu = self.true_anomaly as f64;
+        let semi_latus_rectum = a * (1.0 - e * e);
+        let denominator = 1.0 + e * nu.cos();
+        if e < 0.0 || semi_latus_rectum <= 0.0 || denominator <= 0.0 || mu <= 0.0 {
+            return None;
+        }
+
+        let sign = if self.clockwise { -1.0 } else { 1.0 };
+        let angle = self.argument_of_periapsis as f64 + sign * nu;
+        let radial = DVec2::new(angle.cos(), angle.sin());
+        let tangential = radial.perp();
+
+        let dist = semi_latus_rectum / denominator;
+        let speed_scale = (mu / semi_latus_rectum).sqrt();
+        let vel =
+            speed_scale * e * nu.sin() * radial + sign * speed_scale * denominator * tangential;
+
+        Some(((dist * radial).as_vec2(), vel.as_vec2()))
+    }
+
+    /// `None` for orbits which never come back
+    pub fn period(&self, mu: f32) -> Option<f32> {
+        let a = self.semi_major_axis as f64;
+        if self.eccentricity < 1.0 && a > 0.0 && mu > 0.0 {
+            Some((TAU * (a.powi(3) / mu as f64).sqrt()) as f32)
+        } else {
+            None
+        }
+    }
+
+    pub fn periapsis(&self) -> f32 {
+        self.semi_major_axis * (1.0 - self.eccentricity)
+    }
+
+    /// `None` for orbits which never come back
+    pub fn apoapsis(&self) -> Option<f32> {
+        if self.eccentricity < 1.0 {
+            Some(self.semi_major_axis * (1.0 + self.eccentricity))
+        } else {
+            None
+        }
+    }
+}
+
+/// The body pulling hardest on `pos`, which is usually what it orbits
+pub fn dominant_attractor<'a>(
+    pos: Vec2,
+    bodies: impl Iterator<Item = (Entity, &'a KinematicBody)>,
+) -> Option<Entity> {
+    bodies
+        .map(|(entity, body)| {
+            let dist_sqr = (body.pos - pos).length_squared();
+            (entity, body.mass / dist_sqr.max(f32::EPSILON))
+        })
+        .filter(|(_, pull)| *pull > 0.0)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(entity, _)| entity)
+}
+
+/// Velocity relative to the primary for an orbit with its periapsis at `pos`,
+/// which is a circle when `eccentricity` is 0
+pub fn periapsis_velocity(pos: Vec2, mu: f32, eccentricity: f32, clockwise: bool) -> Vec2 {
+    let dist = pos.length();
+    if dist <= 0.0 || mu <= 0.0 {
+        return Vec2::ZERO;
+    }
+
+    let speed = (mu * (1.0 + eccentricity.max(0.0)) / dist).sqrt();
+    let direction = if clockwise { -pos.perp() } else { pos.perp() };
+    direction / dist * speed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MU: f32 = 100.0;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= 1e-4 * expected.abs().max(1.0),
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn circular_orbit() {
+        let pos = Vec2::new(0.0, 10.0);
+        let vel = Vec2::new(-(MU / 10.0).sqrt(), 0.0);
+        let elements = OrbitalElements::from_state(pos, vel, MU).unwrap();
+
+        assert_close(elements.semi_major_axis, 10.0);
+        assert!(elements.eccentricity < 1e-5);
+        assert!(!elements.clockwise);
+        assert_close(elements.periapsis(), 10.0);
+        assert_close(elements.apoapsis().unwrap(), 10.0);
+        assert_close(
+            elements.period(MU).unwrap(),
+            std::f32::consts::TAU * (1000.0 / MU).sqrt(),
+        );
+        assert_eq!(periapsis_velocity(pos, MU, 0.0, false), vel);
+    }
+
+    #[test]
+    fn elliptical_orbit() {
+        // periapsis 10 and eccentricity 0.5 gives apoapsis 30
+        let pos = Vec2::new(10.0, 0.0);
+        let vel = periapsis_velocity(pos, MU, 0.5, true);
+        let elements = OrbitalElements::from_state(pos, vel, MU).unwrap();
+
+        assert_close(elements.semi_major_axis, 20.0);
+        assert_close(elements.eccentricity, 0.5);
+        assert_close(elements.argument_of_periapsis, 0.0);
+        assert_close(elements.true_anomaly, 0.0);
+        assert!(elements.clockwise);
+        assert_close(elements.periapsis(), 10.0);
+        assert_close(elements.apoapsis().unwrap(), 30.0);
+
+        // halfway round, at apoapsis on the other side of the primary
+        let apoapsis = OrbitalElements {
+            true_anomaly: std::f32::consts::PI,
+            ..elements
+        };
+        let (pos, vel) = apoapsis.to_state(MU).unwrap();
+        assert_close(pos.x, -30.0);
+        assert!(pos.y.abs() < 1e-3);
+        // vis-viva: v² = mu (2/r - 1/a)
+        assert_close(vel.length_squared(), MU * (2.0 / 30.0 - 1.0 / 20.0));
+        // clockwise, so moving up on the left side
+        assert!(vel.y > 0.0);
+    }
+
+    #[test]
+    fn radial_motion_has_no_elements() {
+        let pos = Vec2::new(10.0, 0.0);
+        assert!(OrbitalElements::from_state(pos, Vec2::new(3.0, 0.0), MU).is_none());
+        assert!(OrbitalElements::from_state(Vec2::ZERO, Vec2::Y, MU).is_none());
+    }
+}
@@ -1,9 +1,23 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
 use bevy_ecs::prelude::*;
-use egui_macroquad::macroquad::prelude::*;
+use glam::Vec2;
+
+#[cfg(feature = "gui")]
+use egui_macroquad::{
+    egui,
+    macroquad::prelude::{draw_circle, draw_line, Color, RED, WHITE},
+};
 
+#[cfg(feature = "gui")]
+use crate::camera::CameraRes;
 use crate::{
-    physics::{KinematicBody, Preview, G},
-    trails::Trail,
+    physics::{
+        integrate_bodies, AdaptiveDT, BodySoftening, CumulativeMass, GravitySolver, Integrator,
+        KinematicBody, Paused, PhysicsToggles, Preview, Softening, DT, G,
+    },
+    trails::{RelativeTrails, Trail},
 };
 
 pub struct PreviewTrailTick {
@@ -22,42 +36,577 @@ impl Default for PreviewTrailTick {
     }
 }
 
-pub fn preview_gravity_sys(
-    query_set: QuerySet<(
-        Query<(&mut KinematicBody, &Trail), With<Preview>>,
-        Query<&KinematicBody, Without<Preview>>,
+// limits the pairwise force evaluations per predicted path, so that big
+// scenes get shorter paths instead of freezing
+const PATH_BUDGET: usize = 500_000;
+const MAX_PATH_STEPS: usize = 20_000;
+const MAX_PATH_POINTS: usize = 2_000;
+/// How often paths are predicted again while the simulation runs
+const REFRESH_FRAMES: usize = 30;
+/// Predictions of scenes with more bodies than this use Barnes-Hut, even if
+/// the simulation itself doesn't
+const BARNES_HUT_BODIES: usize = 200;
+
+/// The physics settings a prediction is stepped with
+#[derive(Clone, Copy)]
+pub struct PredictionSettings {
+    pub g: f32,
+    pub dt: f32,
+    pub integrator: Integrator,
+    pub gravity_solver: GravitySolver,
+}
+
+impl PredictionSettings {
+    pub fn new(
+        g: f32,
+        dt: f32,
+        integrator: Integrator,
+        gravity_solver: GravitySolver,
+        body_count: usize,
+    ) -> Self {
+        let gravity_solver = GravitySolver {
+            barnes_hut: gravity_solver.barnes_hut || body_count > BARNES_HUT_BODIES,
+            ..gravity_solver
+        };
+
+        PredictionSettings {
+            g,
+            dt,
+            integrator,
+            gravity_solver,
+        }
+    }
+
+    /// How many steps a path through a scene of `body_count` bodies can take
+    /// within `PATH_BUDGET`
+    fn max_steps(&self, body_count: usize) -> usize {
+        let body_count = body_count.max(1);
+        let step_cost = if self.gravity_solver.barnes_hut {
+            body_count * (body_count.ilog2() as usize + 1)
+        } else {
+            body_count * body_count
+        };
+
+        (PATH_BUDGET / step_cost).min(MAX_PATH_STEPS)
+    }
+}
+
+/// Where and when a predicted body first touches another one
+#[derive(Clone, Copy, Debug)]
+pub struct Impact {
+    /// Relative to the frame the prediction was made in
+    pub pos: Vec2,
+    pub time: f32,
+}
+
+/// A copy of the scene which is stepped forward on its own, so that a
+/// predicted body feels the other bodies move and pull on each other.
+/// Collisions between the copied bodies themselves are ignored.
+pub struct PredictedScene {
+    bodies: Vec<KinematicBody>,
+    softenings: Vec<f32>,
+    /// The body that trails are drawn relative to, if any
+    relative: Option<usize>,
+    /// Time since the scene was copied
+    pub time: f32,
+    pub steps: usize,
+    pub impact: Option<Impact>,
+}
+
+impl PredictedScene {
+    /// Copies `bodies`, which should leave out the body being predicted
+    pub fn new<'a>(
+        bodies: impl Iterator<Item = (Entity, &'a KinematicBody, f32)>,
+        relative: Option<Entity>,
+    ) -> Self {
+        let mut scene = PredictedScene {
+            bodies: Vec::new(),
+            softenings: Vec::new(),
+            relative: None,
+            time: 0.0,
+            steps: 0,
+            impact: None,
+        };
+
+        for (entity, body, softening) in bodies {
+            if Some(entity) == relative {
+                scene.relative = Some(scene.bodies.len());
+            }
+            scene.bodies.push(body.clone());
+            scene.softenings.push(softening);
+        }
+
+        scene
+    }
+
+    /// The predicted position of the body trails are drawn relative to
+    pub fn frame_origin(&self) -> Vec2 {
+        self.relative
+            .map(|i| self.bodies[i].pos)
+            .unwrap_or(Vec2::ZERO)
+    }
+
+    /// Steps the scene and `body` forward together, stopping once `body` hits
+    /// another body or the steps run out. Returns false if it has stopped.
+    pub fn step(
+        &mut self,
+        body: &mut KinematicBody,
+        softening: f32,
+        settings: &PredictionSettings,
+    ) -> bool {
+        let body_count = self.bodies.len() + 1;
+        if self.impact.is_some() || self.steps >= settings.max_steps(body_count) {
+            return false;
+        }
+
+        let softenings = self
+            .softenings
+            .iter()
+            .copied()
+            .chain(std::iter::once(softening))
+            .collect::<Vec<_>>();
+        let mut bodies = self
+            .bodies
+            .iter_mut()
+            .chain(std::iter::once(body))
+            .collect::<Vec<_>>();
+        let masses = bodies.iter().map(|body| body.mass).collect::<Vec<_>>();
+
+        let field = |positions: &[Vec2]| {
+            let sources = positions
+                .iter()
+                .zip(&masses)
+                .zip(&softenings)
+                .map(|((pos, mass), softening)| CumulativeMass::new(*pos, *mass, *softening))
+                .collect::<Vec<_>>();
+
+            settings
+                .gravity_solver
+                .field(settings.g, &sources, &sources)
+        };
+
+        let positions = bodies.iter().map(|body| body.pos).collect::<Vec<_>>();
+        for (body, accel) in bodies.iter_mut().zip(field(&positions)) {
+            body.force += accel * body.mass;
+        }
+
+        integrate_bodies(settings.integrator, settings.dt, &mut bodies, field);
+        self.time += settings.dt;
+        self.steps += 1;
+
+        let body = bodies.pop().unwrap();
+        let hit = bodies
+            .iter()
+            .any(|other| (other.pos - body.pos).length() < other.radius + body.radius);
+        if hit {
+            body.vel = Vec2::ZERO;
+            self.impact = Some(Impact {
+                pos: body.pos - self.frame_origin(),
+                time: self.time,
+            });
+        }
+
+        !hit
+    }
+}
+
+/// Steps every preview body forward along with its own copy of the scene,
+/// which is taken when the preview is first seen
+pub fn preview_prediction_sys(
+    mut query_set: QuerySet<(
+        Query<
+            (
+                Entity,
+                &mut KinematicBody,
+                Option<&Trail>,
+                Option<&BodySoftening>,
+                Option<&mut PredictedScene>,
+            ),
+            With<Preview>,
+        >,
+        Query<(Entity, &KinematicBody, Option<&BodySoftening>), Without<Preview>>,
     )>,
+    dt: Res<DT>,
     g: Res<G>,
+    softening: Res<Softening>,
+    integrator: Res<Integrator>,
+    gravity_solver: Res<GravitySolver>,
+    physics_toggles: Res<PhysicsToggles>,
+    relative_trails_body: Res<RelativeTrails>,
+    mut commands: Commands,
 ) {
-    let preview_query = query_set.q0();
-    let body_query = query_set.q1();
-
-    unsafe {
-        for (mut preview_body, trail) in preview_query.iter_unsafe() {
-            if trail.points.len() == trail.max_len {
-                preview_body.vel = Vec2::new(0.0, 0.0);
-                continue;
+    if !physics_toggles.integration {
+        return;
+    }
+
+    let body_count = query_set.q1().iter().count() + 1;
+    let settings = PredictionSettings::new(g.0, dt.0, *integrator, *gravity_solver, body_count);
+
+    let mut new_scenes = Vec::new();
+    for (entity, mut body, trail, body_softening, scene) in query_set.q0_mut().iter_mut() {
+        let body_softening = BodySoftening::resolve(body_softening, &softening);
+        match scene {
+            Some(mut scene) => {
+                if trail.is_some_and(|trail| trail.points.len() == trail.max_len) {
+                    body.vel = Vec2::ZERO;
+                    continue;
+                }
+
+                if !scene.step(&mut body, body_softening, &settings) {
+                    body.vel = Vec2::ZERO;
+                }
             }
+            None => new_scenes.push(entity),
+        }
+    }
+
+    for entity in new_scenes {
+        let bodies = query_set.q1().iter().map(|(entity, body, body_softening)| {
+            (
+                entity,
+                body,
+                BodySoftening::resolve(body_softening, &softening),
+            )
+        });
 
-            let mut cumulative_force = Vec2::new(0.0, 0.0);
-            let p1 = preview_body.pos;
-            let m1 = preview_body.mass;
+        let scene = PredictedScene::new(bodies, relative_trails_body.0);
+        commands.entity(entity).insert(scene);
+    }
+}
+
+/// Shows where an existing body is headed, toggled from the inspector
+pub struct TrajectoryPrediction {
+    /// How far ahead to predict, in simulation time
+    pub horizon: f32,
+    /// Simulation time between tick marks
+    pub tick_interval: f32,
+    /// Relative to the frame the prediction was made in
+    pub points: Vec<Vec2>,
+    pub ticks: Vec<(Vec2, f32)>,
+    pub impact: Option<Impact>,
+    /// How far ahead the path actually goes, which can fall short of the
+    /// horizon in big scenes
+    pub predicted_time: f32,
+    /// Set when the settings change so that the path is predicted again even
+    /// if the scene hasn't changed
+    pub stale: bool,
+}
+
+impl Default for TrajectoryPrediction {
+    fn default() -> Self {
+        Self {
+            horizon: 500.0,
+            tick_interval: 50.0,
+            points: Vec::new(),
+            ticks: Vec::new(),
+            impact: None,
+            predicted_time: 0.0,
+            stale: true,
+        }
+    }
+}
 
-            for affecting_body in body_query.iter() {
-                let p2 = affecting_body.pos;
-                let m2 = affecting_body.mass;
+impl TrajectoryPrediction {
+    fn predict(
+        &mut self,
+        mut body: KinematicBody,
+        softening: f32,
+        mut scene: PredictedScene,
+        settings: &PredictionSettings,
+    ) {
+        let body_count = scene.bodies.len() + 1;
+        let steps = if settings.dt > 0.0 {
+            ((self.horizon / settings.dt).ceil() as usize).min(settings.max_steps(body_count))
+        } else {
+            0
+        };
+        let stride = (steps / MAX_PATH_POINTS).max(1);
 
-                let rad = p2 - p1;
-                let rad_sqr_dist = rad.length_squared();
-                let rad_dist = rad_sqr_dist.powf(0.5);
+        self.points.clear();
+        self.ticks.clear();
+        self.points.push(body.pos - scene.frame_origin());
 
-                let current_force = g.0 * m1 * m2 / rad_sqr_dist;
+        let mut next_tick = self.tick_interval;
+        for step in 1..=steps {
+            let moving = scene.step(&mut body, softening, settings);
+            let pos = body.pos - scene.frame_origin();
 
-                cumulative_force.x += current_force * rad.x / rad_dist;
-                cumulative_force.y += current_force * rad.y / rad_dist;
+            if step % stride == 0 || !moving {
+                self.points.push(pos);
+            }
+            if self.tick_interval > 0.0 && scene.time >= next_tick {
+                self.ticks.push((pos, scene.time));
+                next_tick += self.tick_interval;
             }
+            if !moving {
+                break;
+            }
+        }
+
+        self.impact = scene.impact;
+        self.predicted_time = scene.time;
+        self.stale = false;
+    }
+}
+
+/// Predicts the paths of bodies with a `TrajectoryPrediction` again when
+/// something changes. While paused that's any change, and while running it's
+/// bodies being added or removed, settings changing, or every
+/// `REFRESH_FRAMES` frames, since the bodies move every frame.
+pub fn trajectory_prediction_sys(
+    mut predictions: Query<(Entity, &mut TrajectoryPrediction)>,
+    bodies: Query<(Entity, &KinematicBody, Option<&BodySoftening>), Without<Preview>>,
+    // the inspector and the options menu touch bodies and settings every
+    // frame, which defeats change detection, so what the paths depend on is
+    // hashed instead
+    mut last_hashes: Local<(u64, u64)>,
+    mut frames_since_refresh: Local<usize>,
+    (dt, g, softening, integrator, gravity_solver, adaptive_dt): (
+        Res<DT>,
+        Res<G>,
+        Res<Softening>,
+        Res<Integrator>,
+        Res<GravitySolver>,
+        Res<AdaptiveDT>,
+    ),
+    relative_trails_body: Res<RelativeTrails>,
+    paused: Res<Paused>,
+) {
+    if predictions.iter_mut().next().is_none() {
+        return;
+    }
+
+    // everything except where the bodies are, which changes every frame
+    // while running, as does the timestep when it's adaptive
+    let mut hasher = DefaultHasher::new();
+    for (entity, body, body_softening) in bodies.iter() {
+        entity.hash(&mut hasher);
+        for x in [
+            body.mass,
+            body.radius,
+            BodySoftening::resolve(body_softening, &softening),
+        ] {
+            x.to_bits().hash(&mut hasher);
+        }
+    }
+    for x in [g.0, gravity_solver.theta] {
+        x.to_bits().hash(&mut hasher);
+    }
+    if !adaptive_dt.enabled {
+        dt.0.to_bits().hash(&mut hasher);
+    }
+    gravity_solver.barnes_hut.hash(&mut hasher);
+    integrator.label().hash(&mut hasher);
+    relative_trails_body.0.hash(&mut hasher);
+    // so that pausing catches up with the bodies straight away
+    paused.0.hash(&mut hasher);
+    let settings_hash = hasher.finish();
+
+    for (_, body, _) in bodies.iter() {
+        for x in [body.pos.x, body.pos.y, body.vel.x, body.vel.y] {
+            x.to_bits().hash(&mut hasher);
+        }
+    }
+    dt.0.to_bits().hash(&mut hasher);
+    let state_hash = hasher.finish();
+
+    *frames_since_refresh += 1;
+    let scene_changed = if paused.0 {
+        state_hash != last_hashes.1
+    } else {
+        settings_hash != last_hashes.0 || *frames_since_refresh >= REFRESH_FRAMES
+    };
+    *last_hashes = (settings_hash, state_hash);
+    if scene_changed {
+        *frames_since_refresh = 0;
+    }
+
+    let settings = PredictionSettings::new(
+        g.0,
+        dt.0,
+        *integrator,
+        *gravity_solver,
+        bodies.iter().count(),
+    );
+
+    for (entity, mut prediction) in predictions.iter_mut() {
+        if !scene_changed && !prediction.stale {
+            continue;
+        }
+
+        let (body, body_softening) = match bodies.get(entity) {
+            Ok((_, body, body_softening)) => (body.clone(), body_softening),
+            Err(_) => continue,
+        };
+
+        let others = bodies.iter().filter(|(other, _, _)| *other != entity).map(
+            |(other, other_body, other_softening)| {
+                (
+                    other,
+                    other_body,
+                    BodySoftening::resolve(other_softening, &softening),
+                )
+            },
+        );
+        let scene = PredictedScene::new(others, relative_trails_body.0);
+
+        prediction.predict(
+            body,
+            BodySoftening::resolve(body_softening, &softening),
+            scene,
+            &settings,
+        );
+    }
+}
+
+#[cfg(feature = "gui")]
+/// World space length of `pixels` on screen, so that markers keep their size
+/// when zooming
+fn screen_length(camera_res: &CameraRes, pixels: f32) -> f32 {
+    (camera_res.camera.screen_to_world(Vec2::new(pixels, 0.0))
+        - camera_res.camera.screen_to_world(Vec2::ZERO))
+    .length()
+}
+
+#[cfg(feature = "gui")]
+fn draw_label(
+    egui_ctx: &egui::Context,
+    camera_res: &CameraRes,
+    id: egui::Id,
+    pos: Vec2,
+    text: String,
+    color: egui::Color32,
+) {
+    let screen_pos = camera_res.camera.world_to_screen(pos) / egui_ctx.pixels_per_point();
+    egui::Area::new(id)
+        .fixed_pos(egui::pos2(screen_pos.x + 16.0, screen_pos.y))
+        .interactable(false)
+        .show(egui_ctx, |ui| {
+            ui.colored_label(color, text);
+        });
+}
+
+#[cfg(feature = "gui")]
+fn draw_impact(
+    egui_ctx: &egui::Context,
+    camera_res: &CameraRes,
+    id: egui::Id,
+    pos: Vec2,
+    time: f32,
+) {
+    let size = screen_length(camera_res, 12.0);
+    let thickness = size / 4.0;
+    draw_line(
+        pos.x - size,
+        pos.y - size,
+        pos.x + size,
+        pos.y + size,
+        thickness,
+        RED,
+    );
+    draw_line(
+        pos.x - size,
+        pos.y + size,
+        pos.x + size,
+        pos.y - size,
+        thickness,
+        RED,
+    );
+
+    draw_label(
+        egui_ctx,
+        camera_res,
+        id,
+        pos,
+        format!("Impact in {:.1}", time),
+        egui::Color32::RED,
+    );
+}
+
+#[cfg(feature = "gui")]
+fn relative_pos(relative_trails_body: &RelativeTrails, bodies: &Query<&KinematicBody>) -> Vec2 {
+    relative_trails_body
+        .0
+        .and_then(|entity| bodies.get(entity).ok())
+        .map(|body| body.pos)
+        .unwrap_or(Vec2::ZERO)
+}
+
+#[cfg(feature = "gui")]
+/// Marks where each preview is predicted to hit another body
+pub fn draw_preview_impacts_sys(
+    query: Query<&PredictedScene, With<Preview>>,
+    relative_bodies: Query<&KinematicBody>,
+    relative_trails_body: Res<RelativeTrails>,
+    camera_res: Res<CameraRes>,
+    egui_ctx: Res<egui::Context>,
+) {
+    let relative_pos = relative_pos(&relative_trails_body, &relative_bodies);
+
+    for (i, impact) in query.iter().filter_map(|scene| scene.impact).enumerate() {
+        let id = egui::Id::new(("preview_impact", i));
+        draw_impact(
+            &egui_ctx,
+            &camera_res,
+            id,
+            impact.pos + relative_pos,
+            impact.time,
+        );
+    }
+}
+
+#[cfg(feature = "gui")]
+pub fn draw_trajectory_predictions_sys(
+    query: Query<(Entity, &TrajectoryPrediction)>,
+    relative_bodies: Query<&KinematicBody>,
+    relative_trails_body: Res<RelativeTrails>,
+    camera_res: Res<CameraRes>,
+    egui_ctx: Res<egui::Context>,
+) {
+    let relative_pos = relative_pos(&relative_trails_body, &relative_bodies);
+    let thickness = screen_length(&camera_res, 2.0);
+    let tick_size = screen_length(&camera_res, 6.0);
+
+    for (entity, prediction) in query.iter() {
+        let points_len = prediction.points.len();
+        for (i, segment) in prediction.points.windows(2).enumerate() {
+            // fades out towards the end of the horizon
+            let alpha = 1.0 - 0.75 * i as f32 / points_len as f32;
+            let (p1, p2) = (segment[0] + relative_pos, segment[1] + relative_pos);
+            draw_line(
+                p1.x,
+                p1.y,
+                p2.x,
+                p2.y,
+                thickness,
+                Color::new(0.6, 1.0, 0.6, alpha),
+            );
+        }
+
+        for (i, (pos, time)) in prediction.ticks.iter().enumerate() {
+            let pos = *pos + relative_pos;
+            draw_circle(pos.x, pos.y, tick_size / 2.0, WHITE);
+
+            let id = egui::Id::new(("trajectory_tick", entity, i));
+            draw_label(
+                &egui_ctx,
+                &camera_res,
+                id,
+                pos,
+                format!("+{:.0}", time),
+                egui::Color32::LIGHT_GRAY,
+            );
+        }
 
-            preview_body.force += cumulative_force;
+        if let Some(impact) = prediction.impact {
+            let id = egui::Id::new(("trajectory_impact", entity));
+            draw_impact(
+                &egui_ctx,
+                &camera_res,
+                id,
+                impact.pos + relative_pos,
+                impact.time,
+            );
         }
     }
 }
@@ -0,0 +1,244 @@
+use glam::Vec2;
+
+use crate::physics::{gravity_accel, pair_softening_sqr, CumulativeMass};
+
+// stops subdividing when many bodies share (almost) the same position
+const MAX_DEPTH: usize = 32;
+
+struct Node {
+    center: Vec2,
+    half_size: f32,
+    cumulative: CumulativeMass,
+    children: Option<[usize; 4]>,
+    bodies: Vec<CumulativeMass>,
+}
+
+impl Node {
+    fn new(center: Vec2, half_size: f32) -> Self {
+        Node {
+            center,
+            half_size,
+            cumulative: CumulativeMass::new(center, 0.0, 0.0),
+            children: None,
+            bodies: Vec::new(),
+        }
+    }
+
+    fn add_mass(&mut self, body: &CumulativeMass) {
+        let total_mass = self.cumulative.mass + body.mass;
+        if total_mass > 0.0 {
+            self.cumulative.pos =
+                (self.cumulative.pos * self.cumulative.mass + body.pos * body.mass) / total_mass;
+            self.cumulative.softening_sqr = (self.cumulative.softening_sqr * self.cumulative.mass
+                + body.softening_sqr * body.mass)
+                / total_mass;
+        } else {
+            self.cumulative.pos = body.pos;
+            self.cumulative.softening_sqr = body.softening_sqr;
+        }
+        self.cumulative.mass = total_mass;
+    }
+
+    fn quadrant(&self, point: Vec2) -> usize {
+        (point.x >= self.center.x) as usize + 2 * (point.y >= self.center.y) as usize
+    }
+
+    fn contains(&self, point: Vec2) -> bool {
+        (point - self.center).abs().max_element() <= self.half_size
+    }
+}
+
+/// Barnes-Hut quadtree over a set of point masses
+pub struct QuadTree {
+    nodes: Vec<Node>,
+}
+
+impl QuadTree {
+    pub fn new(bodies: &[CumulativeMass]) -> Self {
+        let (min, max) = bodies.iter().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), body| (min.min(body.pos), max.max(body.pos)),
+        );
+
+        let (center, half_size) = if bodies.is_empty() {
+            (Vec2::new(0.0, 0.0), 1.0)
+        } else {
+            (
+                (min + max) / 2.0,
+                ((max - min).max_element() / 2.0).max(1.0),
+            )
+        };
+
+        let mut tree = QuadTree {
+            nodes: vec![Node::new(center, half_size)],
+        };
+
+        for body in bodies {
+            tree.insert(*body);
+        }
+
+        tree
+    }
+
+    fn subdivide(&mut self, index: usize) -> [usize; 4] {
+        let center = self.nodes[index].center;
+        let quarter = self.nodes[index].half_size / 2.0;
+
+        let first_child = self.nodes.len();
+        for i in 0..4 {
+            let x_sign = if i % 2 == 1 { 1.0 } else { -1.0 };
+            let y_sign = if i / 2 == 1 { 1.0 } else { -1.0 };
+            let child_center = center + Vec2::new(x_sign, y_sign) * quarter;
+            self.nodes.push(Node::new(child_center, quarter));
+        }
+
+        let children = [
+            first_child,
+            first_child + 1,
+            first_child + 2,
+            first_child + 3,
+        ];
+        self.nodes[index].children = Some(children);
+        children
+    }
+
+    fn insert(&mut self, body: CumulativeMass) {
+        let mut index = 0;
+        let mut depth = 0;
+
+        loop {
+            self.nodes[index].add_mass(&body);
+
+            let children = match self.nodes[index].children {
+                Some(children) => children,
+                None if self.nodes[index].bodies.is_empty() || depth >= MAX_DEPTH => {
+                    self.nodes[index].bodies.push(body);
+                    return;
+                }
+                None => {
+                    let existing = std::mem::take(&mut self.nodes[index].bodies);
+                    let children = self.subdivide(index);
+                    for existing_body in existing {
+                        let quadrant = self.nodes[index].quadrant(existing_body.pos);
+                        let child = &mut self.nodes[children[quadrant]];
+                        child.add_mass(&existing_body);
+                        child.bodies.push(existing_body);
+                    }
+                    children
+                }
+            };
+
+            index = children[self.nodes[index].quadrant(body.pos)];
+            depth += 1;
+        }
+    }
+
+    /// Approximate gravitational acceleration at `point`. Nodes smaller than
+    /// `theta` times their distance are treated as a single mass, and bodies
+    /// exactly at `point` are skipped.
+    pub fn accel(&self, point: &CumulativeMass, theta: f32, g: f32) -> Vec2 {
+        let point_softening_sqr = point.softening_sqr;
+        let point = point.pos;
+
+        let mut accel = Vec2::new(0.0, 0.0);
+        let mut stack = vec![0];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+
+            match node.children {
+                Some(children) => {
+                    let dist = (node.cumulative.pos - point).length();
+                    if !node.contains(point) && 2.0 * node.half_size < theta * dist {
+                        let softening_sqr =
+                            pair_softening_sqr(point_softening_sqr, node.cumulative.softening_sqr);
+                        accel += gravity_accel(
+                            point,
+                            node.cumulative.pos,
+                            node.cumulative.mass,
+                            g,
+                            softening_sqr,
+                        );
+                    } else {
+                        stack.extend(children);
+                    }
+                }
+                None => {
+                    for body in node.bodies.iter().filter(|body| body.pos != point) {
+                        let softening_sqr =
+                            pair_softening_sqr(point_softening_sqr, body.softening_sqr);
+                        accel += gravity_accel(point, body.pos, body.mass, g, softening_sqr);
+                    }
+                }
+            }
+        }
+
+        accel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::physics::{CumulativeMass, GravitySolver};
+
+    // spread over a disc like a sunflower head, so the tree gets uneven leaves
+    fn disc(count: usize) -> Vec<CumulativeMass> {
+        (0..count)
+            .map(|i| {
+                let angle = i as f32 * 2.399_963;
+                let dist = 10.0 * (i as f32).sqrt();
+                let pos = dist * glam::Vec2::new(angle.cos(), angle.sin());
+                CumulativeMass::new(pos, 1.0 + (i % 7) as f32, 0.5)
+            })
+            .collect()
+    }
+
+    /// Total error of the approximate field relative to the total direct field
+    fn error(theta: f32, bodies: &[CumulativeMass]) -> f32 {
+        let direct = GravitySolver {
+            barnes_hut: false,
+            theta,
+        }
+        .field(100.0, bodies, bodies);
+        let barnes_hut = GravitySolver {
+            barnes_hut: true,
+            theta,
+        }
+        .field(100.0, bodies, bodies);
+
+        let total_error = direct
+            .iter()
+            .zip(&barnes_hut)
+            .map(|(direct, barnes_hut)| (*direct - *barnes_hut).length())
+            .sum::<f32>();
+        total_error / direct.iter().map(|a| a.length()).sum::<f32>()
+    }
+
+    #[test]
+    fn barnes_hut_matches_direct_within_tolerance() {
+        let bodies = disc(500);
+        assert!(error(0.5, &bodies) < 0.02);
+        assert!(error(1.0, &bodies) < 0.15);
+    }
+
+    #[test]
+    fn zero_theta_opens_every_node() {
+        let bodies = disc(200);
+        assert!(error(0.0, &bodies) < 1e-4);
+    }
+
+    #[test]
+    fn coincident_bodies_stop_subdividing() {
+        let mut bodies = vec![CumulativeMass::new(glam::Vec2::ZERO, 1.0, 0.0); 10];
+        bodies.push(CumulativeMass::new(glam::Vec2::new(10.0, 0.0), 1.0, 0.0));
+        let field = GravitySolver {
+            barnes_hut: true,
+            theta: 0.5,
+        }
+        .field(1.0, &bodies, &bodies);
+
+        // the stacked bodies skip each other, so they only feel the lone one
+        assert!((field[0] - glam::Vec2::new(0.01, 0.0)).length() < 1e-6);
+        assert!((field[10] - glam::Vec2::new(-0.1, 0.0)).length() < 1e-6);
+    }
+}
@@ -0,0 +1,459 @@
+use bevy_ecs::prelude::*;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use slotmap::{DefaultKey, Key, KeyData};
+
+use crate::{
+    color::Color,
+    error::SimError,
+    history::History,
+    physics::{
+        AdaptiveDT, BodyRestitution, BodySoftening, CollisionMode, Fragmentation, GravitySolver,
+        Integrator, KinematicBody, PhysicsToggles, Preview, Restitution, Softening, Steps, DT, G,
+    },
+    scripting::{RhaiBody, RhaiID, RhaiRes, ScriptSource},
+    selection::{FollowBody, InspectedEntity},
+    trails::{RelativeTrails, Trail},
+};
+
+/// Bumped whenever a scene saved by an older build would be read incorrectly
+pub const SCENE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct SceneBody {
+    #[serde(default)]
+    pub id: Option<u64>,
+    pub pos: [f32; 2],
+    pub vel: [f32; 2],
+    #[serde(default)]
+    pub accel: [f32; 2],
+    pub mass: f32,
+    pub radius: f32,
+    #[serde(default = "default_registered")]
+    pub registered: bool,
+    #[serde(default)]
+    pub color: Option<[f32; 4]>,
+    #[serde(default)]
+    pub trail_length: Option<usize>,
+    #[serde(default)]
+    pub softening: Option<f32>,
+    #[serde(default)]
+    pub restitution: Option<f32>,
+}
+
+fn default_registered() -> bool {
+    true
+}
+
+fn default_steps() -> usize {
+    1
+}
+
+fn default_restitution() -> f32 {
+    0.5
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SceneCamera {
+    pub target: [f32; 2],
+    pub zoom: f32,
+}
+
+/// Everything needed to put the simulation back the way it was
+#[derive(Serialize, Deserialize)]
+pub struct Scene {
+    pub version: u32,
+    pub g: f32,
+    pub dt: f32,
+    pub toggles: PhysicsToggles,
+    // scenes saved before these were added load with the defaults
+    #[serde(default = "default_steps")]
+    pub steps: usize,
+    #[serde(default)]
+    pub softening: f32,
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    #[serde(default)]
+    pub collision_mode: CollisionMode,
+    #[serde(default)]
+    pub fragmentation: Fragmentation,
+    #[serde(default)]
+    pub integrator: Integrator,
+    #[serde(default)]
+    pub gravity_solver: GravitySolver,
+    #[serde(default)]
+    pub adaptive_dt: AdaptiveDT,
+    #[serde(default)]
+    pub camera: Option<SceneCamera>,
+    #[serde(default)]
+    pub script: String,
+    pub bodies: Vec<SceneBody>,
+}
+
+pub enum SceneAction {
+    Save(String),
+    Load(String),
+    /// Lets the user pick a file to load, since the browser can't read paths
+    #[cfg(target_arch = "wasm32")]
+    Open,
+}
+
+/// Scene saves and loads requested by the UI or scripts, handled by `scene_sys`
+pub struct SceneIO {
+    pub path: String,
+    pub pending: Vec<SceneAction>,
+}
+
+impl Default for SceneIO {
+    fn default() -> Self {
+        Self {
+            path: "scene.json".to_string(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl Scene {
+    pub fn capture(world: &mut World) -> Self {
+        let bodies = world
+            .query_filtered::<(
+                &KinematicBody,
+                Option<&RhaiID>,
+                Option<&RhaiBody>,
+                Option<&Color>,
+                Option<&Trail>,
+                Option<&BodySoftening>,
+                Option<&BodyRestitution>,
+            ), Without<Preview>>()
+            .iter(world)
+            .map(
+                |(body, id, registered, color, trail, softening, restitution)| SceneBody {
+                    id: id.map(|id| id.0.data().as_ffi()),
+                    pos: body.pos.into(),
+                    vel: body.vel.into(),
+                    accel: body.accel.into(),
+                    mass: body.mass,
+                    radius: body.radius,
+                    registered: registered.is_some(),
+                    color: color.map(|color| [color.r, color.g, color.b, color.a]),
+                    trail_length: trail.map(|trail| trail.max_len),
+                    softening: softening.map(|softening| softening.0),
+                    restitution: restitution.map(|restitution| restitution.0),
+                },
+            )
+            .collect();
+
+        // there's no camera without a window
+        #[cfg(not(feature = "gui"))]
+        let camera = None;
+        #[cfg(feature = "gui")]
+        let camera = world
+            .get_resource::<crate::camera::CameraRes>()
+            .map(|camera_res| SceneCamera {
+                target: camera_res.camera.target.into(),
+                zoom: camera_res.camera.zoom.y,
+            });
+
+        let script = world
+            .get_resource::<ScriptSource>()
+            .map(|source| source.code.read().unwrap().clone())
+            .unwrap_or_default();
+
+        Scene {
+            version: SCENE_VERSION,
+            g: world.get_resource::<G>().unwrap().0,
+            dt: world.get_resource::<DT>().unwrap().0,
+            toggles: *world.get_resource::<PhysicsToggles>().unwrap(),
+            steps: world.get_resource::<Steps>().unwrap().0,
+            softening: world.get_resource::<Softening>().unwrap().0,
+            restitution: world.get_resource::<Restitution>().unwrap().0,
+            collision_mode: *world.get_resource::<CollisionMode>().unwrap(),
+            fragmentation: *world.get_resource::<Fragmentation>().unwrap(),
+            integrator: *world.get_resource::<Integrator>().unwrap(),
+            gravity_solver: *world.get_resource::<GravitySolver>().unwrap(),
+            adaptive_dt: *world.get_resource::<AdaptiveDT>().unwrap(),
+            camera,
+            script,
+            bodies,
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SimError> {
+        let scene: Scene = serde_json::from_str(json)?;
+        if scene.version > SCENE_VERSION {
+            return Err(SimError::Scene(format!(
+                "scene version {} is newer than the supported version {}",
+                scene.version, SCENE_VERSION
+            )));
+        }
+
+        Ok(scene)
+    }
+
+    pub fn to_json(&self) -> Result<String, SimError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Replaces every body, the script, and the saved settings. The script is
+    /// run again so that its functions exist, but the bodies it adds and the
+    /// settings it changes are replaced by the saved ones.
+    pub fn restore(self, world: &mut World) {
+        let entities = world.query::<Entity>().iter(world).collect::<Vec<_>>();
+        for entity in entities {
+            world.despawn(entity);
+        }
+
+        world.insert_resource(InspectedEntity(None));
+        world.insert_resource(RelativeTrails(None));
+        if world.get_resource::<FollowBody>().is_some() {
+            world.insert_resource(FollowBody(None));
+        }
+
+        let rhai = match world.get_resource::<RhaiRes>() {
+            Some(old_rhai) => old_rhai.restarted(&self.script),
+            None => RhaiRes::default().restarted(&self.script),
+        };
+
+        let mut unregistered = Vec::new();
+        for body in self.bodies {
+            let mut builder = world.spawn();
+            builder.insert(KinematicBody {
+                pos: body.pos.into(),
+                vel: body.vel.into(),
+                accel: body.accel.into(),
+                force: Vec2::ZERO,
+                mass: body.mass,
+                radius: body.radius,
+            });
+
+            if body.registered {
+                builder.insert(RhaiBody);
+            }
+            if let Some([r, g, b, a]) = body.color {
+                builder.insert(Color::new(r, g, b, a));
+            }
+            if let Some(max_len) = body.trail_length {
+                builder.insert(Trail {
+                    max_len,
+                    ..Trail::default()
+                });
+            }
+            if let Some(softening) = body.softening {
+                builder.insert(BodySoftening(softening));
+            }
+            if let Some(restitution) = body.restitution {
+                builder.insert(BodyRestitution(restitution));
+            }
+
+            let entity = builder.id();
+            let saved_key = body
+                .id
+                .map(|id| DefaultKey::from(KeyData::from_ffi(id)))
+                .filter(|key| !rhai.existing_bodies.read().unwrap().contains_key(key));
+            match saved_key {
+                Some(key) => {
+                    rhai.existing_bodies.write().unwrap().insert(key, entity);
+                    world.entity_mut(entity).insert(RhaiID(key));
+                }
+                None => unregistered.push(entity),
+            }
+        }
+        // after every saved id is taken, so that new ids can't clash with them
+        for entity in unregistered {
+            let key = rhai.register_body(entity);
+            world.entity_mut(entity).insert(RhaiID(key));
+        }
+
+        if let Some(mut source) = world.get_resource_mut::<ScriptSource>() {
+            *source.code.write().unwrap() = self.script.clone();
+            source.should_run = false;
+            source.output = Some(rhai.output.clone());
+        }
+        #[cfg(target_arch = "wasm32")]
+        call_js("set_editor_code", &[&self.script]);
+
+        world.insert_resource(rhai);
+        if let Some(mut history) = world.get_resource_mut::<History>() {
+            history.clear();
+        }
+        world.insert_resource(G(self.g));
+        world.insert_resource(DT(self.dt));
+        world.insert_resource(self.toggles);
+        world.insert_resource(Steps(self.steps));
+        world.insert_resource(Softening(self.softening));
+        world.insert_resource(Restitution(self.restitution));
+        world.insert_resource(self.collision_mode);
+        world.insert_resource(self.fragmentation);
+        world.insert_resource(self.integrator);
+        world.insert_resource(self.gravity_solver);
+        world.insert_resource(self.adaptive_dt);
+
+        #[cfg(feature = "gui")]
+        if let (Some(saved_camera), Some(mut camera_res)) = (
+            self.camera,
+            world.get_resource_mut::<crate::camera::CameraRes>(),
+        ) {
+            let aspect_ratio = camera_res.screen_size.x / camera_res.screen_size.y;
+            camera_res.camera.target = saved_camera.target.into();
+            camera_res.camera.zoom = Vec2::new(saved_camera.zoom / aspect_ratio, saved_camera.zoom);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn call_js(name: &str, args: &[&str]) -> Option<wasm_bindgen::JsValue> {
+    use wasm_bindgen::{JsCast, JsValue};
+
+    let function = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str(name))
+        .ok()?
+        .dyn_into::<js_sys::Function>()
+        .ok()?;
+    let args = args
+        .iter()
+        .map(|arg| JsValue::from_str(arg))
+        .collect::<js_sys::Array>();
+
+    function.apply(&JsValue::NULL, &args).ok()
+}
+
+fn save_scene(world: &mut World, path: &str) -> Result<(), SimError> {
+    #[cfg(target_arch = "wasm32")]
+    if let Some(code) = call_js("get_editor_code", &[]).and_then(|code| code.as_string()) {
+        if let Some(source) = world.get_resource::<ScriptSource>() {
+            *source.code.write().unwrap() = code;
+        }
+    }
+
+    let json = Scene::capture(world).to_json()?;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    std::fs::write(path, json)?;
+    #[cfg(target_arch = "wasm32")]
+    call_js("download_text", &[path, &json]);
+
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load_scene(world: &mut World, path: &str) -> Result<(), SimError> {
+    let json = std::fs::read_to_string(path)?;
+    Scene::from_json(&json)?.restore(world);
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn load_scene(_world: &mut World, path: &str) -> Result<(), SimError> {
+    Err(SimError::Scene(format!(
+        "can't read {} from the browser, use Scene > Open instead",
+        path
+    )))
+}
+
+pub fn scene_sys(world: &mut World) {
+    let actions = std::mem::take(&mut world.get_resource_mut::<SceneIO>().unwrap().pending);
+
+    let mut errors = Vec::new();
+    for action in actions {
+        let result = match &action {
+            SceneAction::Save(path) => save_scene(world, path)
+                .map_err(|e| format!("Couldn't save scene to {}: {}", path, e)),
+            SceneAction::Load(path) => load_scene(world, path)
+                .map_err(|e| format!("Couldn't load scene from {}: {}", path, e)),
+            #[cfg(target_arch = "wasm32")]
+            SceneAction::Open => {
+                call_js("open_text_file", &[".json"]);
+                Ok(())
+            }
+        };
+
+        if let Err(e) = result {
+            errors.push(e);
+        }
+    }
+
+    // the file picker finishes reading some time after Open is clicked
+    #[cfg(target_arch = "wasm32")]
+    if let Some(json) = call_js("take_opened_file", &[]).and_then(|json| json.as_string()) {
+        match Scene::from_json(&json) {
+            Ok(scene) => scene.restore(world),
+            Err(e) => errors.push(format!("Couldn't open scene: {}", e)),
+        }
+    }
+
+    if !errors.is_empty() {
+        let output = world.get_resource::<RhaiRes>().unwrap().output.clone();
+        for e in errors {
+            output.write().unwrap().push_str(&format!("{}\n", e));
+        }
+        if let Some(mut source) = world.get_resource_mut::<ScriptSource>() {
+            source.output = Some(output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+
+    const SCRIPT: &str = r#"
+        add_body(#{pos: vec(0.0, 0.0), vel: vec(0.0, 0.0), mass: 1000.0, radius: 10.0});
+        add_body(#{pos: vec(100.0, 0.0), vel: vec(0.0, 30.0), mass: 1.0, radius: 1.0});
+        let update = |ids, bodies| { print(ids.length); };
+    "#;
+
+    #[test]
+    fn json_round_trip() {
+        let mut saved = Simulation::default();
+        saved.world.insert_resource(G(50.0));
+        saved.world.insert_resource(Integrator::RK4);
+        saved.load_script(SCRIPT);
+        saved.step();
+        let json = Scene::capture(&mut saved.world).to_json().unwrap();
+
+        let mut loaded = Simulation::default();
+        Scene::from_json(&json).unwrap().restore(&mut loaded.world);
+
+        assert_eq!(loaded.world.get_resource::<G>().unwrap().0, 50.0);
+        assert_eq!(
+            *loaded.world.get_resource::<Integrator>().unwrap(),
+            Integrator::RK4
+        );
+        let source = loaded.world.get_resource::<ScriptSource>().unwrap();
+        assert_eq!(*source.code.read().unwrap(), SCRIPT);
+
+        let mut expected = saved.bodies();
+        let mut actual = loaded.bodies();
+        expected.sort_by_key(|(id, _)| *id);
+        actual.sort_by_key(|(id, _)| *id);
+        assert_eq!(actual.len(), 2);
+        for ((expected_id, expected), (actual_id, actual)) in expected.iter().zip(&actual) {
+            assert!(actual_id.is_some());
+            assert_eq!(actual_id, expected_id);
+            assert_eq!(actual.pos, expected.pos);
+            assert_eq!(actual.vel, expected.vel);
+            assert_eq!(actual.accel, expected.accel);
+            assert_eq!(actual.mass, expected.mass);
+            assert_eq!(actual.radius, expected.radius);
+        }
+
+        // the script's update function still runs for the loaded bodies, from
+        // the second frame on like after pressing Run
+        loaded.take_output();
+        loaded.run_scripting();
+        loaded.run_scripting();
+        assert_eq!(loaded.take_output().trim(), "2");
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let mut scene = Scene::capture(&mut Simulation::default().world);
+        scene.version = SCENE_VERSION + 1;
+        let json = scene.to_json().unwrap();
+
+        match Scene::from_json(&json) {
+            Err(SimError::Scene(msg)) => assert!(msg.contains("newer")),
+            _ => panic!("a scene from a newer version should be rejected"),
+        }
+    }
+}
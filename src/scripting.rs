@@ -1,12 +1,25 @@
 use bevy_ecs::prelude::*;
-use egui_macroquad::macroquad::prelude::*;
+use glam::Vec2;
 use rhai::{Engine, Scope};
 
-use crate::ui::graphs::Graph;
+use crate::broadphase::SpatialHash;
+use crate::color::Color;
+use crate::diagnostics::{Diagnostics, DiagnosticsRes};
+use crate::orbit::periapsis_velocity;
+use self::drawing::{DrawLayer, Drawing};
+use self::limits::{FrameDeadline, ScriptLimits};
+use crate::trails::RelativeTrails;
+use crate::scene::{SceneAction, SceneIO};
+use crate::selection::{FollowBody, InspectedEntity};
+use self::controls::ScriptControls;
+use self::graphs::{Graph, Graphs};
 
 use crate::{
-    physics::{KinematicBody, PhysicsToggles, G, Paused, DT},
-    ui::code_editor::CodeEditor,
+    physics::{
+        AdaptiveDT, BodyRestitution, BodySoftening, CollisionMode, Fragmentation, GravitySolver,
+        Integrator, KinematicBody, PhysicsToggles, Preview, Restitution, SimTime, Softening, Steps,
+        G, Paused, DT,
+    },
 };
 
 use slotmap::{DefaultKey, SlotMap, KeyData};
@@ -16,6 +29,10 @@ use std::{
     sync::{Arc, RwLock},
 };
 
+pub mod controls;
+pub mod drawing;
+pub mod graphs;
+pub mod limits;
 pub mod samples;
 mod util;
 
@@ -23,22 +40,161 @@ pub struct RhaiID(pub DefaultKey);
 
 pub enum RhaiCommand {
     UpdateBody { id: DefaultKey, params: rhai::Map }, // TODO: set timestep, add graph, etc.
-    DeleteBody { id: DefaultKey },
+    DeleteBody { id: DefaultKey, entity: Entity },
     SetG(f32),
     SetDT(f32),
+    SetSteps(usize),
+    SetSoftening(f32),
+    SetBodySoftening { id: DefaultKey, softening: Option<f32> },
     SetCollisions(bool),
+    SetCollisionMode(CollisionMode),
+    SetRestitution(f32),
+    SetBodyRestitution { id: DefaultKey, restitution: Option<f32> },
+    SetFragmentation(bool),
+    SetFragmentationThreshold(f32),
+    SetFragmentCount(usize),
     SetIntegration(bool),
+    SetIntegrator(Integrator),
+    SetBarnesHut(bool),
+    SetBarnesHutTheta(f32),
+    SetAdaptiveDT(bool),
+    SetAdaptiveDTBounds { min: f32, max: f32 },
+    SetAdaptiveDTTolerance(f32),
     Draw { params: rhai::Map },
-    Export,
+    SetDrawLayer(Option<String>),
+    CreateLayer(String),
+    ClearLayer(String),
+    DeleteLayer(String),
+    SetLayerVisible { name: String, visible: bool },
+    ToggleLayer(String),
+    SaveScene(String),
+    LoadScene(String),
     SetPaused(bool),
     // AddToGraph { name: String, point: f32 },
 }
 
+/// Something that happened to the simulation which scripts can react to by
+/// defining an `on_*` closure, delivered in order before `update` is called
+pub enum ScriptEvent {
+    /// Both bodies survive when collisions bounce instead of merging
+    Collision {
+        survivor: DefaultKey,
+        absorbed: DefaultKey,
+    },
+    BodyAdded(DefaultKey),
+    BodyDeleted(DefaultKey),
+    Click(Vec2),
+}
+
+impl ScriptEvent {
+    const HOOKS: [&'static str; 4] = ["on_collision", "on_body_added", "on_body_deleted", "on_click"];
+
+    fn hook(&self) -> &'static str {
+        match self {
+            ScriptEvent::Collision { .. } => "on_collision",
+            ScriptEvent::BodyAdded(_) => "on_body_added",
+            ScriptEvent::BodyDeleted(_) => "on_body_deleted",
+            ScriptEvent::Click(_) => "on_click",
+        }
+    }
+}
+
+/// Body changes made by a single `update` or hook call. Reads see the changes
+/// straight away, and they're written back together once the call returns.
+#[derive(Default)]
+pub struct BodyTransaction {
+    active: bool,
+    bodies: BTreeMap<DefaultKey, KinematicBody>,
+    pending: BTreeMap<DefaultKey, KinematicBody>,
+}
+
+impl BodyTransaction {
+    pub fn get(&self, id: &DefaultKey) -> Option<&KinematicBody> {
+        self.pending.get(id).or_else(|| self.bodies.get(id))
+    }
+
+    /// Returns false if there's no call running or the body isn't known yet,
+    /// in which case the update has to go through the command queue
+    fn update(&mut self, id: DefaultKey, params: &rhai::Map) -> bool {
+        if !self.active {
+            return false;
+        }
+
+        match self.get(&id).cloned() {
+            Some(mut body) => {
+                body.apply_rhai_params(params);
+                self.pending.insert(id, body);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn commit(&mut self) -> Vec<(DefaultKey, KinematicBody)> {
+        let pending = std::mem::take(&mut self.pending);
+        self.bodies.extend(pending.iter().map(|(id, body)| (*id, body.clone())));
+        pending.into_iter().collect()
+    }
+
+    fn rollback(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// The `bodies` passed to `update`. Reads go through the transaction, so
+/// `bodies[id]` sees changes made earlier in the same call like `get_body`.
 #[derive(Clone)]
-pub enum DrawFn {
-    Draw(Arc<dyn Fn() -> DrawFn + Send + Sync>),
-    Finished,
+pub struct BodyView {
+    transaction: Arc<RwLock<BodyTransaction>>,
+    existing_bodies: Arc<RwLock<BTreeMap<DefaultKey, Entity>>>,
 }
+
+impl BodyView {
+    fn get(&mut self, id: DefaultKey) -> rhai::Dynamic {
+        if !self.existing_bodies.read().unwrap().contains_key(&id) {
+            return rhai::Dynamic::UNIT;
+        }
+
+        self.transaction
+            .read()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .map(rhai::Dynamic::from)
+            .unwrap_or(rhai::Dynamic::UNIT)
+    }
+
+    fn contains(&mut self, id: DefaultKey) -> bool {
+        self.existing_bodies.read().unwrap().contains_key(&id)
+    }
+
+    fn ids(&mut self) -> Vec<DefaultKey> {
+        self.existing_bodies.read().unwrap().keys().copied().collect()
+    }
+
+    fn len(&mut self) -> i64 {
+        self.existing_bodies.read().unwrap().len() as i64
+    }
+}
+
+/// The script to run and where its output goes. The editor in the app and the
+/// command line both write the code here and set `should_run`.
+pub struct ScriptSource {
+    pub code: Arc<RwLock<String>>,
+    pub should_run: bool,
+    pub output: Option<Arc<RwLock<String>>>,
+}
+
+impl Default for ScriptSource {
+    fn default() -> Self {
+        Self {
+            code: Arc::new(RwLock::new("".to_string())),
+            should_run: false,
+            output: None,
+        }
+    }
+}
+
 pub struct RhaiBody;
 pub struct RhaiRes {
     pub engine: Engine,
@@ -48,8 +204,17 @@ pub struct RhaiRes {
     pub existing_bodies: Arc<RwLock<BTreeMap<DefaultKey, Entity>>>,
     pub names: BTreeMap<String, DefaultKey>,
     pub commands: Arc<RwLock<Vec<RhaiCommand>>>,
-    pub drawings: DrawFn,
-    pub graphs: Arc<RwLock<BTreeMap<String, Graph>>>,
+    pub events: Arc<RwLock<Vec<ScriptEvent>>>,
+    pub transaction: Arc<RwLock<BodyTransaction>>,
+    /// G as the running script sees it, including its own set_g calls
+    pub g: Arc<RwLock<f32>>,
+    pub limits: ScriptLimits,
+    pub deadline: FrameDeadline,
+    /// Drawn for a single frame, replaced every time the commands are run
+    pub drawings: Vec<Drawing>,
+    pub layers: BTreeMap<String, DrawLayer>,
+    pub graphs: Arc<RwLock<Graphs>>,
+    pub controls: Arc<RwLock<ScriptControls>>,
     pub last_code: rhai::AST,
     pub lib_ast: rhai::AST,
     pub should_update: bool,
@@ -61,9 +226,15 @@ impl Default for RhaiRes {
         scope.push("_console_output", String::new());
 
         let mut engine = Engine::new();
-        engine.set_max_expr_depths(0, 0);
 
-        engine.set_optimization_level(rhai::OptimizationLevel::Full);
+        // Full would evaluate calls with constant arguments at compile time,
+        // running commands like delete_all() before the script even starts
+        engine.set_optimization_level(rhai::OptimizationLevel::Simple);
+
+        let limits = ScriptLimits::default();
+        limits.apply(&mut engine);
+        let deadline = FrameDeadline::default();
+        deadline.watch(&mut engine);
 
         let output = Arc::new(RwLock::new(String::new()));
 
@@ -114,34 +285,92 @@ impl Default for RhaiRes {
         engine.register_fn("*", |lhs: Vec2, rhs: i64| lhs * rhs as f32);
         engine.register_fn("*", |lhs: i64, rhs: Vec2| lhs as f32 * rhs);
 
+        engine.register_type::<Color>();
+        engine.register_fn("rgb", |r: i64, g: i64, b: i64| Color::from_rgba_clamped(r, g, b, 255));
+        engine.register_fn("rgba", Color::from_rgba_clamped);
+        engine.register_fn("rgb", |r: f32, g: f32, b: f32| Color::new(r, g, b, 1.0));
+        engine.register_fn("rgba", Color::new);
+
         engine.register_fn("max", |lhs: f32, rhs: f32| lhs.max(rhs));
         engine.register_fn("min", |lhs: f32, rhs: f32| lhs.min(rhs));
 
         engine.register_type::<Graph>()
               .register_fn("to_string", |g: &mut Graph| format!("Graph: {}", g.label));
 
-        let mut graphs = Arc::new(RwLock::new(BTreeMap::<String, Graph>::new()));
+        let graphs = Arc::new(RwLock::new(Graphs::default()));
 
         let graphs_ref = graphs.clone();
         engine.register_fn("new_graph", move |name: String, max_points: i64, r: i64, g: i64, b: i64| {
             if let Ok(mut graphs) = graphs_ref.write() {
                 let g = Graph::new(name.as_str(), max_points as usize, r as u8, g as u8, b as u8);
-                graphs.insert(name.clone(), g);
+                graphs.insert(g);
             }
         });
 
+        let graphs_ref = graphs.clone();
+        engine.register_fn("new_graph", move |name: &str, options: rhai::Map| {
+            graphs_ref.write().unwrap().insert(Graph::from_rhai(name, &options));
+        });
+
         let graphs_ref = graphs.clone();
         engine.register_fn("add_point", move |name: &str, point: f32| {
-            if let Ok(mut graphs) = graphs_ref.write() {
-                if let Some(graph) = graphs.get_mut(name) {
-                    graph.points.push_back(point);
-                    while graph.points.len() > graph.max_points {
-                        graph.points.pop_front();
-                    }
-                }
-            }
+            graphs_ref.write().unwrap().add_point(name, point);
+        });
+
+        let graphs_ref = graphs.clone();
+        engine.register_fn("add_point", move |name: &str, x: f32, y: f32| {
+            graphs_ref.write().unwrap().add_xy_point(name, x, y);
+        });
+
+        let graphs_ref = graphs.clone();
+        engine.register_fn("add_point", move |name: &str, point: Vec2| {
+            graphs_ref.write().unwrap().add_xy_point(name, point.x, point.y);
+        });
+
+        let graphs_ref = graphs.clone();
+        engine.register_fn("set_axis_labels", move |window: &str, x_label: &str, y_label: &str| {
+            let mut graphs = graphs_ref.write().unwrap();
+            let window = graphs.windows.entry(window.to_string()).or_default();
+            window.x_label = x_label.to_string();
+            window.y_label = y_label.to_string();
+        });
+
+        let graphs_ref = graphs.clone();
+        engine.register_fn("set_log_scale", move |window: &str, log_x: bool, log_y: bool| {
+            let mut graphs = graphs_ref.write().unwrap();
+            let window = graphs.windows.entry(window.to_string()).or_default();
+            window.log_x = log_x;
+            window.log_y = log_y;
+        });
+
+        let controls = Arc::new(RwLock::new(ScriptControls::default()));
+
+        let controls_ref = controls.clone();
+        engine.register_fn("slider", move |name: &str, min: f32, max: f32, initial: f32| {
+            controls_ref.write().unwrap().slider(name, min, max, initial)
+        });
+
+        let controls_ref = controls.clone();
+        engine.register_fn("slider", move |name: &str, min: i64, max: i64, initial: i64| {
+            controls_ref
+                .write()
+                .unwrap()
+                .slider(name, min as f32, max as f32, initial as f32)
+        });
+
+        let controls_ref = controls.clone();
+        engine.register_fn("checkbox", move |name: &str, initial: bool| {
+            controls_ref.write().unwrap().checkbox(name, initial)
+        });
+
+        let controls_ref = controls.clone();
+        engine.register_fn("button", move |name: &str, callback: rhai::FnPtr| {
+            controls_ref.write().unwrap().button(name, callback);
         });
 
+        let controls_ref = controls.clone();
+        engine.register_fn("control", move |name: &str| controls_ref.read().unwrap().value(name));
+
         engine.register_type::<Entity>();
         engine.register_type::<DefaultKey>();
         engine.register_type::<SlotMap<DefaultKey, KinematicBody>>();
@@ -150,6 +379,12 @@ impl Default for RhaiRes {
             .register_get("length", |v: &mut Vec<DefaultKey>| v.len() as i64);
         engine.register_iterator::<Vec<DefaultKey>>();
         engine.register_indexer_get(|v: &mut Vec<DefaultKey>, ix: i64| v[ix as usize]);
+        engine
+            .register_type_with_name::<BodyView>("Bodies")
+            .register_indexer_get(BodyView::get)
+            .register_fn("contains", BodyView::contains)
+            .register_fn("keys", BodyView::ids)
+            .register_get("length", BodyView::len);
 
         engine.register_fn("insert", SlotMap::<DefaultKey, KinematicBody>::insert);
         engine.register_fn("get", SlotMap::<DefaultKey, KinematicBody>::get);
@@ -163,73 +398,393 @@ impl Default for RhaiRes {
         let newly_added_bodies = Arc::new(RwLock::new(SlotMap::<DefaultKey, rhai::Map>::new()));
         let existing_bodies = Arc::new(RwLock::new(BTreeMap::<DefaultKey, Entity>::new()));
 
+        let existing_bodies_ref = existing_bodies.clone();
         let new_bodies_ref = newly_added_bodies.clone();
         engine.register_fn("add_body", move |body| {
-            new_bodies_ref.write().unwrap().insert(body)
+            add_new_body(&existing_bodies_ref, &new_bodies_ref, body)
         });
 
         let commands = Arc::new(RwLock::new(Vec::new()));
+        let transaction = Arc::new(RwLock::new(BodyTransaction::default()));
 
         let command_ref = commands.clone();
-        engine.register_fn("update_body", move |id, params| {
-            let mut commands_writer = command_ref.write().unwrap();
-            commands_writer.push(RhaiCommand::UpdateBody { id, params });
+        let transaction_ref = transaction.clone();
+        engine.register_fn("update_body", move |id, params: rhai::Map| {
+            if !transaction_ref.write().unwrap().update(id, &params) {
+                let mut commands_writer = command_ref.write().unwrap();
+                commands_writer.push(RhaiCommand::UpdateBody { id, params });
+            }
         });
 
         let command_ref = commands.clone();
+        let existing_bodies_ref = existing_bodies.clone();
+        let new_bodies_ref = newly_added_bodies.clone();
         engine.register_fn("delete_body", move |id| {
-            let mut commands_writer = command_ref.write().unwrap();
-            commands_writer.push(RhaiCommand::DeleteBody { id });
+            forget_body(&existing_bodies_ref, &new_bodies_ref, &command_ref, id);
         });
 
         let command_ref = commands.clone();
+        let existing_bodies_ref = existing_bodies.clone();
+        let new_bodies_ref = newly_added_bodies.clone();
+        engine.register_fn("delete_all", move || {
+            new_bodies_ref.write().unwrap().clear();
+            let ids = existing_bodies_ref.read().unwrap().keys().copied().collect::<Vec<_>>();
+            for id in ids {
+                forget_body(&existing_bodies_ref, &new_bodies_ref, &command_ref, id);
+            }
+        });
+
+        let command_ref = commands.clone();
+        let existing_bodies_ref = existing_bodies.clone();
+        let new_bodies_ref = newly_added_bodies.clone();
+        let transaction_ref = transaction.clone();
+        engine.register_fn(
+            "delete_where",
+            move |context: rhai::NativeCallContext,
+                  predicate: rhai::FnPtr|
+                  -> Result<(), Box<rhai::EvalAltResult>> {
+                let bodies = {
+                    let transaction = transaction_ref.read().unwrap();
+                    existing_bodies_ref
+                        .read()
+                        .unwrap()
+                        .keys()
+                        .filter_map(|id| transaction.get(id).map(|body| (*id, body.clone())))
+                        .collect::<Vec<_>>()
+                };
+
+                for (id, body) in bodies {
+                    if predicate.call_within_context::<bool>(&context, (body,))? {
+                        forget_body(&existing_bodies_ref, &new_bodies_ref, &command_ref, id);
+                    }
+                }
+
+                Ok(())
+            },
+        );
+
+        let g = Arc::new(RwLock::new(100.0));
+
+        let command_ref = commands.clone();
+        let g_ref = g.clone();
         engine.register_fn("set_g", move |new_g| {
+            *g_ref.write().unwrap() = new_g;
             let mut commands_writer = command_ref.write().unwrap();
             commands_writer.push(RhaiCommand::SetG(new_g));
         });
 
+        // bodies added this run haven't been spawned yet, so look there first
+        let find_body = {
+            let new_bodies_ref = newly_added_bodies.clone();
+            let transaction_ref = transaction.clone();
+            move |id: DefaultKey| -> Result<KinematicBody, Box<rhai::EvalAltResult>> {
+                let new_body = new_bodies_ref.read().unwrap().get(id).cloned();
+                new_body
+                    .map(KinematicBody::from_rhai)
+                    .or_else(|| transaction_ref.read().unwrap().get(&id).cloned())
+                    .ok_or_else(|| "No body with that id".into())
+            }
+        };
+
+        // mu is G * (M + m) everywhere, like add_orbiting_body and the
+        // creation UI, with the orbiting body's mass left out when not given
+        let circular_velocity = {
+            let find_body = find_body.clone();
+            let g_ref = g.clone();
+            move |primary_id: DefaultKey,
+                  pos: Vec2,
+                  mass: f32|
+                  -> Result<Vec2, Box<rhai::EvalAltResult>> {
+                let primary = find_body(primary_id)?;
+                let mu = *g_ref.read().unwrap() * (primary.mass + mass);
+                Ok(primary.vel + periapsis_velocity(pos - primary.pos, mu, 0.0, false))
+            }
+        };
+
+        let circular_velocity_ref = circular_velocity.clone();
+        engine.register_fn(
+            "circular_velocity",
+            move |primary_id: DefaultKey, pos: Vec2| circular_velocity_ref(primary_id, pos, 0.0),
+        );
+        let circular_velocity_ref = circular_velocity.clone();
+        engine.register_fn(
+            "circular_velocity",
+            move |primary_id: DefaultKey, pos: Vec2, mass: f32| {
+                circular_velocity_ref(primary_id, pos, mass)
+            },
+        );
+        engine.register_fn(
+            "circular_velocity",
+            move |primary_id: DefaultKey, pos: Vec2, mass: i64| {
+                circular_velocity(primary_id, pos, mass as f32)
+            },
+        );
+
+        let existing_bodies_ref = existing_bodies.clone();
+        let new_bodies_ref = newly_added_bodies.clone();
+        let g_ref = g.clone();
+        engine.register_fn(
+            "add_orbiting_body",
+            move |primary_id: DefaultKey,
+                  mut body: rhai::Map|
+                  -> Result<DefaultKey, Box<rhai::EvalAltResult>> {
+                let primary = find_body(primary_id)?;
+                let eccentricity = body
+                    .remove("eccentricity")
+                    .and_then(|e| {
+                        e.as_float()
+                            .ok()
+                            .or_else(|| e.as_int().ok().map(|e| e as f32))
+                    })
+                    .unwrap_or(0.0);
+                let clockwise = body
+                    .remove("clockwise")
+                    .and_then(|clockwise| clockwise.as_bool().ok())
+                    .unwrap_or(false);
+                if !body.contains_key("pos") {
+                    return Err("add_orbiting_body needs a pos".into());
+                }
+
+                let new_body = KinematicBody::from_rhai(body.clone());
+                let mu = *g_ref.read().unwrap() * (primary.mass + new_body.mass);
+                let vel = primary.vel
+                    + periapsis_velocity(new_body.pos - primary.pos, mu, eccentricity, clockwise);
+                body.insert("vel".into(), rhai::Dynamic::from(vel));
+
+                Ok(add_new_body(&existing_bodies_ref, &new_bodies_ref, body))
+            },
+        );
+
         let command_ref = commands.clone();
         engine.register_fn("set_dt", move |new_dt| {
             let mut commands_writer = command_ref.write().unwrap();
             commands_writer.push(RhaiCommand::SetDT(new_dt));
         });
 
+        let command_ref = commands.clone();
+        engine.register_fn("set_steps", move |steps: i64| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetSteps(steps.max(1) as usize));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_softening", move |softening| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetSoftening(softening));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_softening", move |id, softening| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetBodySoftening {
+                id,
+                softening: Some(softening),
+            });
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("clear_softening", move |id| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetBodySoftening { id, softening: None });
+        });
+
         let command_ref = commands.clone();
         engine.register_fn("set_collisions", move |enabled| {
             let mut commands_writer = command_ref.write().unwrap();
             commands_writer.push(RhaiCommand::SetCollisions(enabled));
         });
 
+        let command_ref = commands.clone();
+        engine.register_fn("set_collision_mode", move |name: &str| -> Result<(), Box<rhai::EvalAltResult>> {
+            match CollisionMode::from_name(name) {
+                Some(mode) => {
+                    let mut commands_writer = command_ref.write().unwrap();
+                    commands_writer.push(RhaiCommand::SetCollisionMode(mode));
+                    Ok(())
+                }
+                None => {
+                    let names = CollisionMode::ALL.map(CollisionMode::name).join(", ");
+                    Err(format!("Unknown collision mode \"{}\", expected one of: {}", name, names).into())
+                }
+            }
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_restitution", move |restitution| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetRestitution(restitution));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_restitution", move |id, restitution| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetBodyRestitution {
+                id,
+                restitution: Some(restitution),
+            });
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("clear_restitution", move |id| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetBodyRestitution { id, restitution: None });
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_fragmentation", move |enabled| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetFragmentation(enabled));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_fragmentation_threshold", move |threshold| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetFragmentationThreshold(threshold));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_fragment_count", move |count: i64| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetFragmentCount(count.max(2) as usize));
+        });
+
         let command_ref = commands.clone();
         engine.register_fn("set_integration", move |enabled| {
             let mut commands_writer = command_ref.write().unwrap();
             commands_writer.push(RhaiCommand::SetIntegration(enabled));
         });
 
+        let command_ref = commands.clone();
+        engine.register_fn("set_integrator", move |name: &str| -> Result<(), Box<rhai::EvalAltResult>> {
+            match Integrator::from_name(name) {
+                Some(integrator) => {
+                    let mut commands_writer = command_ref.write().unwrap();
+                    commands_writer.push(RhaiCommand::SetIntegrator(integrator));
+                    Ok(())
+                }
+                None => {
+                    let names = Integrator::ALL.map(Integrator::name).join(", ");
+                    Err(format!("Unknown integrator \"{}\", expected one of: {}", name, names).into())
+                }
+            }
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_barnes_hut", move |enabled| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetBarnesHut(enabled));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_barnes_hut_theta", move |theta| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetBarnesHutTheta(theta));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_adaptive_dt", move |enabled| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetAdaptiveDT(enabled));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_adaptive_dt_bounds", move |min, max| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetAdaptiveDTBounds { min, max });
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_adaptive_dt_tolerance", move |tolerance| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetAdaptiveDTTolerance(tolerance));
+        });
+
         let command_ref = commands.clone();
         engine.register_fn("set_paused", move |enabled| {
             let mut commands_writer = command_ref.write().unwrap();
             commands_writer.push(RhaiCommand::SetPaused(enabled));
         });
 
+        let command_ref = commands.clone();
+        engine.register_fn("save_scene", move |path: &str| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SaveScene(path.to_string()));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("load_scene", move |path: &str| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::LoadScene(path.to_string()));
+        });
+
         let command_ref = commands.clone();
         engine.register_fn("draw", move |params| {
             let mut commands_writer = command_ref.write().unwrap();
             commands_writer.push(RhaiCommand::Draw { params });
         });
 
+        let command_ref = commands.clone();
+        engine.register_fn("create_layer", move |name: &str| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::CreateLayer(name.to_string()));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_draw_layer", move |name: &str| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetDrawLayer(Some(name.to_string())));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("reset_draw_layer", move || {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetDrawLayer(None));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("clear_layer", move |name: &str| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::ClearLayer(name.to_string()));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("delete_layer", move |name: &str| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::DeleteLayer(name.to_string()));
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("set_layer_visible", move |name: &str, visible: bool| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::SetLayerVisible {
+                name: name.to_string(),
+                visible,
+            });
+        });
+
+        let command_ref = commands.clone();
+        engine.register_fn("toggle_layer", move |name: &str| {
+            let mut commands_writer = command_ref.write().unwrap();
+            commands_writer.push(RhaiCommand::ToggleLayer(name.to_string()));
+        });
+
         // TODO: Constify via a macro
         let mut lib_code = "
             fn reset_physics() {
                 set_g(100.0);
                 set_collisions(true);
+                set_collision_mode(\"merge\");
+                set_fragmentation(false);
                 set_integration(true);
+                set_integrator(\"verlet\");
+                set_barnes_hut(false);
+                set_adaptive_dt(false);
+                set_steps(1);
+                set_softening(0.0);
             }
         "
         .to_string();
         for field in ["pos", "vel", "accel", "force", "mass", "radius"] {
-            // the set_{field} functions are weird because of the 1-frame
-            // delay and black magic
             lib_code.push_str(&format!(
                 "
                 fn get_{0}(body) {{ get_body(body).{0} }}
@@ -238,8 +793,8 @@ impl Default for RhaiRes {
                 fn add_{0}(body, field) {{ update_body(body, #{{ add_{0}: field }}) }}
                 fn add_{0}(field) {{ update_body(this, #{{ add_{0}: field }} ) }}
 
-                fn set_{0}(body, field) {{ update_body(body, #{{ add_{0}: -body.get_{0}() + field }}) }}
-                fn set_{0}(field) {{ update_body(this, #{{ add_{0}: -this.get_{0}() + field }} ) }}
+                fn set_{0}(body, field) {{ update_body(body, #{{ set_{0}: field }}) }}
+                fn set_{0}(field) {{ update_body(this, #{{ set_{0}: field }}) }}
             ",
                 field
             ));
@@ -254,6 +809,45 @@ impl Default for RhaiRes {
                     thickness: thickness,
                 });
             }
+
+            fn draw_line(start, end, thickness, color) {
+                draw(#{ type: \"line\", start: start, end: end, thickness: thickness, color: color });
+            }
+
+            fn draw_circle(center, radius, color) {
+                draw(#{ type: \"circle\", center: center, radius: radius, color: color });
+            }
+
+            fn draw_circle_outline(center, radius, thickness, color) {
+                draw(#{
+                    type: \"circle\",
+                    center: center,
+                    radius: radius,
+                    filled: false,
+                    thickness: thickness,
+                    color: color,
+                });
+            }
+
+            fn draw_rect(pos, size, color) {
+                draw(#{ type: \"rect\", pos: pos, size: size, color: color });
+            }
+
+            fn draw_polyline(points, thickness, color) {
+                draw(#{ type: \"polyline\", points: points, thickness: thickness, color: color });
+            }
+
+            fn draw_arrow(start, end, thickness, color) {
+                draw(#{ type: \"arrow\", start: start, end: end, thickness: thickness, color: color });
+            }
+
+            fn draw_text(text, pos, size, color) {
+                draw(#{ type: \"text\", text: text, pos: pos, size: size, color: color });
+            }
+
+            fn draw_screen_text(text, pos, size, color) {
+                draw(#{ type: \"text\", text: text, pos: pos, size: size, color: color, screen: true });
+            }
         ");
         let lib_ast = engine.compile(&lib_code).unwrap();
 
@@ -264,17 +858,99 @@ impl Default for RhaiRes {
             newly_added_bodies,
             existing_bodies,
             commands,
+            events: Arc::new(RwLock::new(Vec::new())),
+            transaction,
+            g,
+            limits,
+            deadline,
             names: BTreeMap::new(),
             last_code: rhai::AST::default(),
             lib_ast,
-            drawings: DrawFn::Finished,
+            drawings: Vec::new(),
+            layers: BTreeMap::new(),
             graphs,
+            controls,
             should_update: false,
         }
     }
 }
 
 impl RhaiRes {
+    /// Gives a body spawned outside of a script its own id
+    pub fn register_body(&self, entity: Entity) -> DefaultKey {
+        // hacky way to get a unique key
+        let key = add_new_body(
+            &self.existing_bodies,
+            &self.newly_added_bodies,
+            rhai::Map::default(),
+        );
+        self.newly_added_bodies.write().unwrap().remove(key);
+        self.existing_bodies.write().unwrap().insert(key, entity);
+        key
+    }
+
+    /// Deletes a body the same way as the script's `delete_body`, so that
+    /// `on_body_deleted` hears about it. Returns false if the id isn't known.
+    pub fn delete_body(&self, id: DefaultKey) -> bool {
+        let known = self.existing_bodies.read().unwrap().contains_key(&id);
+        forget_body(
+            &self.existing_bodies,
+            &self.newly_added_bodies,
+            &self.commands,
+            id,
+        );
+        known
+    }
+
+    pub fn push_event(&self, event: ScriptEvent) {
+        self.events.write().unwrap().push(event);
+    }
+
+    pub fn set_limits(&mut self, limits: ScriptLimits) {
+        limits.apply(&mut self.engine);
+        self.limits = limits;
+    }
+
+    /// Restarts the time budget shared by every script call this frame
+    pub fn start_frame(&self) {
+        self.deadline.start_frame(self.limits.frame_budget_ms);
+    }
+
+    /// Whether the script has anything that runs after its first run
+    pub fn has_callbacks(&self) -> bool {
+        std::iter::once("update")
+            .chain(ScriptEvent::HOOKS)
+            .any(|name| self.scope.get_value::<rhai::FnPtr>(name).is_some())
+            || self.controls.read().unwrap().has_buttons()
+    }
+
+    /// A new engine which has run `code` from the start for its functions and
+    /// variables, without the bodies it adds or the commands it queues. Limits
+    /// and control values carry over.
+    pub fn restarted(&self, code: &str) -> Self {
+        let mut rhai = RhaiRes::default();
+        rhai.set_limits(self.limits);
+        {
+            let mut controls = rhai.controls.write().unwrap();
+            *controls = std::mem::take(&mut *self.controls.write().unwrap());
+            controls.begin_run();
+        }
+
+        rhai.start_frame();
+        match rhai.engine.compile_with_scope(&rhai.scope, code) {
+            Ok(ast) => {
+                let ast = ast.merge(&rhai.lib_ast);
+                rhai.run_ast(&ast);
+                rhai.last_code = ast;
+            }
+            Err(e) => *rhai.output.write().unwrap() = e.to_string(),
+        }
+        rhai.newly_added_bodies.write().unwrap().clear();
+        rhai.commands.write().unwrap().clear();
+
+        rhai
+    }
+
     pub fn run_code(&mut self, code: &str) {
         match self.engine.eval_with_scope::<()>(&mut self.scope, code) {
             Ok(_) => {}
@@ -282,7 +958,7 @@ impl RhaiRes {
                 .output
                 .write()
                 .unwrap()
-                .push_str(e.to_string().as_str()),
+                .push_str(self.limits.describe_error(&e).as_str()),
         }
         
         self.graphs.write().unwrap().clear();
@@ -298,38 +974,87 @@ impl RhaiRes {
                 .output
                 .write()
                 .unwrap()
-                .push_str(e.to_string().as_str()),
+                .push_str(self.limits.describe_error(&e).as_str()),
         }
     }
 }
 
+/// Queues a body to be spawned. The slot map starts over whenever the script
+/// does, while ids loaded from scenes and snapshots go straight into
+/// `existing_bodies`, so keys already in use are skipped.
+fn add_new_body(
+    existing_bodies: &RwLock<BTreeMap<DefaultKey, Entity>>,
+    newly_added_bodies: &RwLock<SlotMap<DefaultKey, rhai::Map>>,
+    body: rhai::Map,
+) -> DefaultKey {
+    let existing_bodies = existing_bodies.read().unwrap();
+    let mut newly_added_bodies = newly_added_bodies.write().unwrap();
+    loop {
+        let key = newly_added_bodies.insert(body.clone());
+        if !existing_bodies.contains_key(&key) {
+            return key;
+        }
+        // removing bumps the slot's version, so the next key is different
+        newly_added_bodies.remove(key);
+    }
+}
+
+/// Forgets a body straight away, so that `exists` and `get_body` stop seeing it
+/// for the rest of the frame, and queues its despawn
+fn forget_body(
+    existing_bodies: &RwLock<BTreeMap<DefaultKey, Entity>>,
+    newly_added_bodies: &RwLock<SlotMap<DefaultKey, rhai::Map>>,
+    commands: &RwLock<Vec<RhaiCommand>>,
+    id: DefaultKey,
+) {
+    newly_added_bodies.write().unwrap().remove(id);
+    let removed = existing_bodies.write().unwrap().remove(&id);
+    if let Some(entity) = removed {
+        commands.write().unwrap().push(RhaiCommand::DeleteBody { id, entity });
+    }
+}
+
 pub fn run_code_sys(
-    mut code_editor: ResMut<CodeEditor>,
+    mut source: ResMut<ScriptSource>,
     mut rhai: ResMut<RhaiRes>,
+    sim_time: Res<SimTime>,
+    g: Res<G>,
+    bodies: Query<(&KinematicBody, &RhaiID)>,
     mut commands: Commands,
 ) {
-    if code_editor.should_run {
+    if source.should_run {
+        // so that helpers like circular_velocity can find bodies already in the scene
+        *rhai.g.write().unwrap() = g.0;
+        rhai.transaction.write().unwrap().bodies = bodies
+            .iter()
+            .map(|(body, id)| (id.0, body.clone()))
+            .collect();
         rhai.graphs.write().unwrap().clear();
+        rhai.graphs.write().unwrap().time = sim_time.0;
+        rhai.controls.write().unwrap().begin_run();
+        rhai.layers.clear();
+        rhai.events.write().unwrap().clear();
         rhai.output.write().unwrap().clear();
-        code_editor.should_run = false;
+        source.should_run = false;
         rhai.should_update = false;
 
-        let code_lock = code_editor.code.read().unwrap();
+        let code_lock = source.code.read().unwrap();
         let ast = match rhai.engine.compile_with_scope(&rhai.scope, &*code_lock) {
             Ok(ast) => ast.merge(&rhai.lib_ast),
             Err(e) => {
                 *rhai.output.write().unwrap() = e.to_string();
                 std::mem::drop(e);
                 std::mem::drop(code_lock);
-                code_editor.output = Some(rhai.output.clone());
+                source.output = Some(rhai.output.clone());
                 return;
             }
         };
         std::mem::drop(code_lock);
         rhai.scope.remove::<rhai::FnPtr>("update");
+        rhai.start_frame();
         rhai.run_ast(&ast);
         rhai.last_code = ast;
-        code_editor.output = Some(rhai.output.clone());
+        source.output = Some(rhai.output.clone());
 
         for (key, added_body) in rhai.newly_added_bodies.write().unwrap().drain() {
             let registered = added_body
@@ -339,6 +1064,20 @@ pub fn run_code_sys(
 
             let mut builder = commands.spawn();
 
+            if let Some(softening) = added_body
+                .get("softening")
+                .and_then(|softening| softening.clone().try_cast::<f32>())
+            {
+                builder.insert(BodySoftening(softening));
+            }
+
+            if let Some(restitution) = added_body
+                .get("restitution")
+                .and_then(|restitution| restitution.clone().try_cast::<f32>())
+            {
+                builder.insert(BodyRestitution(restitution));
+            }
+
             builder.insert(KinematicBody::from_rhai(added_body));
             if registered {
                 builder.insert(RhaiBody);
@@ -347,6 +1086,7 @@ pub fn run_code_sys(
             let entity = builder.id();
             rhai.existing_bodies.write().unwrap().insert(key, entity);
             commands.entity(entity).insert(RhaiID(key));
+            rhai.push_event(ScriptEvent::BodyAdded(key));
         }
     }
 }
@@ -354,14 +1094,44 @@ pub fn run_code_sys(
 pub fn run_rhai_commands_sys(
     mut rhai_res: ResMut<RhaiRes>,
     mut query: Query<&mut KinematicBody, With<RhaiBody>>,
-    mut g: ResMut<G>,
-    mut dt: ResMut<DT>,
-    mut physics_toggles: ResMut<PhysicsToggles>,
+    (
+        mut g,
+        mut dt,
+        mut steps,
+        mut softening,
+        mut collision_mode,
+        mut restitution,
+        mut fragmentation,
+        mut physics_toggles,
+        mut integrator,
+        mut gravity_solver,
+        mut adaptive_dt,
+    ): (
+        ResMut<G>,
+        ResMut<DT>,
+        ResMut<Steps>,
+        ResMut<Softening>,
+        ResMut<CollisionMode>,
+        ResMut<Restitution>,
+        ResMut<Fragmentation>,
+        ResMut<PhysicsToggles>,
+        ResMut<Integrator>,
+        ResMut<GravitySolver>,
+        ResMut<AdaptiveDT>,
+    ),
+    mut inspected_entity: ResMut<InspectedEntity>,
+    mut follow_body: Option<ResMut<FollowBody>>,
+    mut relative_trails_body: ResMut<RelativeTrails>,
     mut paused: ResMut<Paused>,
+    mut scene_io: ResMut<SceneIO>,
+    mut commands: Commands,
 ) {
+    let rhai_res = &mut *rhai_res;
     let body_reader = rhai_res.existing_bodies.read().unwrap();
     let mut rhai_commands = rhai_res.commands.write().unwrap();
-    let mut draw_fn = DrawFn::Finished;
+    let mut drawings = Vec::new();
+    // draw() calls go to this layer instead of the current frame
+    let mut draw_layer = None;
     for command in rhai_commands.drain(..) {
         match command {
             RhaiCommand::UpdateBody { id, params } => {
@@ -370,63 +1140,47 @@ pub fn run_rhai_commands_sys(
                     .and_then(|entity| query.get_mut(*entity).ok());
 
                 if let Some(mut body) = body_opt {
-                    macro_rules! generate_set_add {
-                        ($field:ident, $str:expr, $ty:ty) => {
-                            let set = params
-                                .get(concat!("set_", $str))
-                                .map(|dynamic| dynamic.clone().try_cast())
-                                .flatten();
-
-                            if let Some(field) = set {
-                                body.$field = field;
-                            }
-
-                            let add = params
-                                .get(concat!("add_", $str))
-                                .map(|dynamic| dynamic.clone().try_cast::<$ty>())
-                                .flatten();
-
-                            if let Some(field) = add {
-                                body.$field += field;
-                            }
-                        };
-                    }
-
-                    generate_set_add!(pos, "pos", Vec2);
-                    generate_set_add!(vel, "vel", Vec2);
-                    generate_set_add!(accel, "accel", Vec2);
-                    generate_set_add!(force, "force", Vec2);
-                    generate_set_add!(mass, "mass", f32);
-                    generate_set_add!(radius, "radius", f32);
+                    body.apply_rhai_params(&params);
                 }
             }
-            RhaiCommand::Draw { params } => {
-                match params.get("type") {
-                    Some(s) if s.clone_cast::<String>().as_str() == "line" => {
-                        let start = params
-                            .get("start")
-                            .and_then(|d| d.clone().try_cast::<Vec2>())
-                            .unwrap_or(Vec2::ZERO);
-                        let end = params
-                            .get("end")
-                            .and_then(|d| d.clone().try_cast::<Vec2>())
-                            .unwrap_or(Vec2::ZERO);
-                        let thickness = params
-                            .get("thickness")
-                            .and_then(|d| d.clone().try_cast::<f32>())
-                            .unwrap_or(1.0);
-                        // let color = params
-                        //     .get("color")
-                        //     .and_then(|d| d.clone().try_cast::<Color>())
-                        //     .unwrap_or(WHITE);
-
-                        draw_fn = DrawFn::Draw(Arc::new(move || {
-                            draw_line(start.x, start.y, end.x, end.y, thickness, WHITE);
-                            draw_fn.clone()
-                        }))
+            RhaiCommand::Draw { params } => match Drawing::from_rhai(&params) {
+                Ok(drawing) => match &draw_layer {
+                    Some(name) => match rhai_res.layers.get_mut(name) {
+                        Some(layer) => layer.drawings.push(drawing),
+                        None => rhai_res
+                            .output
+                            .write()
+                            .unwrap()
+                            .push_str(&format!("draw: no layer named \"{}\"\n", name)),
                     },
-                    Some(_) => todo!(),
-                    None => todo!(),
+                    None => drawings.push(drawing),
+                },
+                Err(e) => {
+                    rhai_res.output.write().unwrap().push_str(&format!("draw: {}\n", e));
+                }
+            },
+            RhaiCommand::SetDrawLayer(name) => {
+                draw_layer = name;
+            }
+            RhaiCommand::CreateLayer(name) => {
+                rhai_res.layers.entry(name).or_default();
+            }
+            RhaiCommand::ClearLayer(name) => {
+                if let Some(layer) = rhai_res.layers.get_mut(&name) {
+                    layer.drawings.clear();
+                }
+            }
+            RhaiCommand::DeleteLayer(name) => {
+                rhai_res.layers.remove(&name);
+            }
+            RhaiCommand::SetLayerVisible { name, visible } => {
+                if let Some(layer) = rhai_res.layers.get_mut(&name) {
+                    layer.visible = visible;
+                }
+            }
+            RhaiCommand::ToggleLayer(name) => {
+                if let Some(layer) = rhai_res.layers.get_mut(&name) {
+                    layer.visible = !layer.visible;
                 }
             }
             RhaiCommand::SetG(new_g) => {
@@ -435,19 +1189,94 @@ pub fn run_rhai_commands_sys(
             RhaiCommand::SetDT(new_dt) => {
                 dt.0 = new_dt;
             }
-            RhaiCommand::Export => {
-                let text = "asdf";
-                let dl_link = format!("data:text/plain;charset=utf-8,{text}");
-                println!("{}", dl_link);
+            RhaiCommand::SetSteps(new_steps) => {
+                steps.0 = new_steps;
+            }
+            RhaiCommand::SetSoftening(new_softening) => {
+                softening.0 = new_softening;
+            }
+            RhaiCommand::SetBodySoftening { id, softening } => {
+                if let Some(entity) = body_reader.get(&id) {
+                    match softening {
+                        Some(softening) => {
+                            commands.entity(*entity).insert(BodySoftening(softening));
+                        }
+                        None => {
+                            commands.entity(*entity).remove::<BodySoftening>();
+                        }
+                    }
+                }
+            }
+            RhaiCommand::SaveScene(path) => {
+                scene_io.pending.push(SceneAction::Save(path));
+            }
+            RhaiCommand::LoadScene(path) => {
+                scene_io.pending.push(SceneAction::Load(path));
             }
             RhaiCommand::SetCollisions(enabled_or_disabled) => {
                 physics_toggles.collisions = enabled_or_disabled;
             }
+            RhaiCommand::SetCollisionMode(new_mode) => {
+                *collision_mode = new_mode;
+            }
+            RhaiCommand::SetRestitution(new_restitution) => {
+                restitution.0 = new_restitution;
+            }
+            RhaiCommand::SetFragmentation(enabled_or_disabled) => {
+                fragmentation.enabled = enabled_or_disabled;
+            }
+            RhaiCommand::SetFragmentationThreshold(threshold) => {
+                fragmentation.threshold = threshold;
+            }
+            RhaiCommand::SetFragmentCount(count) => {
+                fragmentation.fragments = count;
+            }
+            RhaiCommand::SetBodyRestitution { id, restitution } => {
+                if let Some(entity) = body_reader.get(&id) {
+                    match restitution {
+                        Some(restitution) => {
+                            commands.entity(*entity).insert(BodyRestitution(restitution));
+                        }
+                        None => {
+                            commands.entity(*entity).remove::<BodyRestitution>();
+                        }
+                    }
+                }
+            }
             RhaiCommand::SetIntegration(enabled_or_disabled) => {
                 physics_toggles.integration = enabled_or_disabled;
             }
-            RhaiCommand::DeleteBody { id: _ } => {
-                todo!()
+            RhaiCommand::SetIntegrator(new_integrator) => {
+                *integrator = new_integrator;
+            }
+            RhaiCommand::SetBarnesHut(enabled_or_disabled) => {
+                gravity_solver.barnes_hut = enabled_or_disabled;
+            }
+            RhaiCommand::SetBarnesHutTheta(theta) => {
+                gravity_solver.theta = theta;
+            }
+            RhaiCommand::SetAdaptiveDT(enabled_or_disabled) => {
+                adaptive_dt.enabled = enabled_or_disabled;
+            }
+            RhaiCommand::SetAdaptiveDTBounds { min, max } => {
+                adaptive_dt.min = min;
+                adaptive_dt.max = max;
+            }
+            RhaiCommand::SetAdaptiveDTTolerance(tolerance) => {
+                adaptive_dt.tolerance = tolerance;
+            }
+            RhaiCommand::DeleteBody { id, entity } => {
+                despawn_deleted_body(
+                    &mut commands,
+                    &rhai_res.events,
+                    id,
+                    entity,
+                    (
+                        &mut inspected_entity,
+                        follow_body.as_deref_mut(),
+                        &mut relative_trails_body,
+                    ),
+                );
             }
             RhaiCommand::SetPaused(enabled_or_disabled) => {
                 paused.0 = enabled_or_disabled;
@@ -457,118 +1286,365 @@ pub fn run_rhai_commands_sys(
 
     std::mem::drop(body_reader);
     std::mem::drop(rhai_commands);
-    rhai_res.drawings = draw_fn;
+    rhai_res.drawings = drawings;
+}
+
+fn despawn_deleted_body(
+    commands: &mut Commands,
+    events: &RwLock<Vec<ScriptEvent>>,
+    id: DefaultKey,
+    entity: Entity,
+    (inspected_entity, follow_body, relative_trails_body): (
+        &mut InspectedEntity,
+        Option<&mut FollowBody>,
+        &mut RelativeTrails,
+    ),
+) {
+    commands.entity(entity).despawn();
+    events.write().unwrap().push(ScriptEvent::BodyDeleted(id));
+
+    if inspected_entity.0 == Some(entity) {
+        inspected_entity.0 = None;
+    }
+    if let Some(follow_body) = follow_body {
+        if follow_body.0 == Some(entity) {
+            follow_body.0 = None;
+        }
+    }
+    if relative_trails_body.0 == Some(entity) {
+        relative_trails_body.0 = None;
+    }
+}
+
+/// Despawns the bodies deleted by `update`, hooks and buttons straight away
+/// instead of with the rest of their commands next frame, so that they don't
+/// take part in another physics step
+pub fn apply_deletions_sys(
+    rhai: Res<RhaiRes>,
+    mut inspected_entity: ResMut<InspectedEntity>,
+    mut follow_body: Option<ResMut<FollowBody>>,
+    mut relative_trails_body: ResMut<RelativeTrails>,
+    mut commands: Commands,
+) {
+    let mut rhai_commands = rhai.commands.write().unwrap();
+    rhai_commands.retain(|command| match *command {
+        RhaiCommand::DeleteBody { id, entity } => {
+            despawn_deleted_body(
+                &mut commands,
+                &rhai.events,
+                id,
+                entity,
+                (
+                    &mut inspected_entity,
+                    follow_body.as_deref_mut(),
+                    &mut relative_trails_body,
+                ),
+            );
+            false
+        }
+        _ => true,
+    });
 }
 
 pub fn run_script_update_sys(
     mut rhai: ResMut<RhaiRes>,
-    mut code_editor: ResMut<CodeEditor>,
+    mut source: ResMut<ScriptSource>,
     dt: Res<DT>,
     paused: Res<Paused>,
-    registered_bodies: Query<(Entity, &KinematicBody, &RhaiID)>,
+    sim_time: Res<SimTime>,
+    (g, softening): (Res<G>, Res<Softening>),
+    mut registered_bodies: Query<(Entity, &mut KinematicBody, &RhaiID)>,
+    body_softenings: Query<(&RhaiID, &BodySoftening)>,
+    other_bodies: Query<
+        (&KinematicBody, Option<&BodySoftening>),
+        (Without<RhaiID>, Without<Preview>),
+    >,
+    diagnostics_res: Res<DiagnosticsRes>,
 ) {
-    if let Some(update_fn) = rhai.scope.get_value::<rhai::FnPtr>("update") {
-        if !rhai.should_update {
-            rhai.should_update = true;
-            let code_lock = code_editor.code.read().unwrap();
-            let ast = match rhai.engine.compile_with_scope(&rhai.scope, &*code_lock) {
-                Ok(ast) => ast.merge(&rhai.lib_ast),
-                Err(e) => {
-                    *rhai.output.write().unwrap() = e.to_string();
-                    std::mem::drop(e);
-                    std::mem::drop(code_lock);
-                    code_editor.output = Some(rhai.output.clone());
-                    return;
-                }
-            };
-            rhai.last_code = ast;
-            return;
-        }
+    let update_fn = rhai.scope.get_value::<rhai::FnPtr>("update");
+    let has_hooks = ScriptEvent::HOOKS
+        .iter()
+        .any(|hook| rhai.scope.get_value::<rhai::FnPtr>(hook).is_some());
 
-        {
-            let existing_bodies_lock = rhai.existing_bodies.clone();
-            let mut existing_bodies = existing_bodies_lock.write().unwrap();
-            for (e, _, id) in registered_bodies.iter() {
-                existing_bodies.insert(id.0, e);
+    let has_buttons = rhai.controls.read().unwrap().has_buttons();
+
+    if update_fn.is_none() && !has_hooks && !has_buttons {
+        rhai.events.write().unwrap().clear();
+        return;
+    }
+
+    if !rhai.should_update {
+        rhai.should_update = true;
+        let code_lock = source.code.read().unwrap();
+        let ast = match rhai.engine.compile_with_scope(&rhai.scope, &*code_lock) {
+            Ok(ast) => ast.merge(&rhai.lib_ast),
+            Err(e) => {
+                *rhai.output.write().unwrap() = e.to_string();
+                std::mem::drop(e);
+                std::mem::drop(code_lock);
+                source.output = Some(rhai.output.clone());
+                return;
             }
+        };
+        rhai.last_code = ast;
+        return;
+    }
+
+    {
+        let existing_bodies_lock = rhai.existing_bodies.clone();
+        let mut existing_bodies = existing_bodies_lock.write().unwrap();
+        for (e, _, id) in registered_bodies.iter_mut() {
+            existing_bodies.insert(id.0, e);
         }
+    }
 
-        let registered_bodies_map = {
-            Arc::new(
-                registered_bodies
-                .iter()
-                .map(|(e, b, _)| (e, b.clone()))
-                .collect::<BTreeMap<Entity, KinematicBody>>(),
-                )
-        };
+    let registered_bodies_map = {
+        Arc::new(
+            registered_bodies
+            .iter_mut()
+            .map(|(e, b, _)| (e, b.clone()))
+            .collect::<BTreeMap<Entity, KinematicBody>>(),
+            )
+    };
+
+    let existing_bodies = rhai.existing_bodies.clone();
+    existing_bodies
+        .write()
+        .unwrap()
+        .retain(|_, e| registered_bodies_map.contains_key(&e));
+
+    {
+        *rhai.g.write().unwrap() = g.0;
+        let body_reader = existing_bodies.read().unwrap();
+        let mut transaction = rhai.transaction.write().unwrap();
+        transaction.bodies = body_reader
+            .iter()
+            .filter_map(|(k, e)| registered_bodies_map.get(e).map(|body| (*k, body.clone())))
+            .collect();
+        transaction.rollback();
+    }
 
-        let existing_bodies = rhai.existing_bodies.clone();
-        existing_bodies
-            .write()
-            .unwrap()
-            .retain(|_, e| registered_bodies_map.contains_key(&e));
-        let existing_body_map = {
+    {
+        let existing_bodies = existing_bodies.clone();
+        let registered_bodies_map = registered_bodies_map.clone();
+        rhai.engine.register_fn("exists", move |id| {
+            let body_reader = existing_bodies.read().unwrap();
+            body_reader.get(&id).is_some_and(|entity| {
+                registered_bodies_map
+                    .get(entity)
+                    .is_some()
+            })
+        });
+    }
+
+    {
+        let (keys, circles): (Vec<_>, Vec<_>) = {
             let body_reader = existing_bodies.read().unwrap();
             body_reader
                 .iter()
-                .map(|(k, e)| {
-                    (
-                        *k,
-                        registered_bodies_map
+                .filter_map(|(k, e)| {
+                    registered_bodies_map
                         .get(e)
-                        .cloned()
-                        .map(rhai::Dynamic::from)
-                        .unwrap_or(rhai::Dynamic::UNIT),
-                        )
+                        .map(|body| (*k, (body.pos, body.radius)))
                 })
-            .collect::<BTreeMap<DefaultKey, rhai::Dynamic>>()
+                .unzip()
         };
+        let broadphase = Arc::new((keys, SpatialHash::new(circles)));
+
+        let broadphase_ref = broadphase.clone();
+        rhai.engine.register_fn("overlapping", move |pos: Vec2, radius: f32| {
+            let (keys, spatial_hash) = &*broadphase_ref;
+            spatial_hash
+                .overlapping(pos, radius)
+                .into_iter()
+                .map(|i| rhai::Dynamic::from(keys[i]))
+                .collect::<rhai::Array>()
+        });
 
-        let existing_body_ids = {
+        let registered_bodies_map = registered_bodies_map.clone();
+        let existing_bodies = existing_bodies.clone();
+        rhai.engine.register_fn("overlapping", move |id: DefaultKey| {
+            let (keys, spatial_hash) = &*broadphase;
             let body_reader = existing_bodies.read().unwrap();
-            body_reader.iter().map(|(k, _)| *k).collect::<Vec<_>>()
-        };
-
-        {
-            let existing_bodies = existing_bodies.clone();
-            let registered_bodies_map = registered_bodies_map.clone();
-            rhai.engine.register_fn("exists", move |id| {
-                let body_reader = existing_bodies.read().unwrap();
-                body_reader.get(&id).is_some_and(|entity| {
-                    registered_bodies_map
-                        .get(entity)
-                        .is_some()
-                })
-            });
-        }
+            match body_reader.get(&id).and_then(|e| registered_bodies_map.get(e)) {
+                Some(body) => spatial_hash
+                    .overlapping(body.pos, body.radius)
+                    .into_iter()
+                    .map(|i| keys[i])
+                    .filter(|key| *key != id)
+                    .map(rhai::Dynamic::from)
+                    .collect::<rhai::Array>(),
+                None => rhai::Array::new(),
+            }
+        });
+    }
 
+    {
+        let existing_bodies = existing_bodies.clone();
+        let transaction = rhai.transaction.clone();
         rhai.engine.register_fn("get_body", move |id| {
-            let body_reader = existing_bodies.read().unwrap();
-            body_reader
+            if !existing_bodies.read().unwrap().contains_key(&id) {
+                return rhai::Dynamic::UNIT;
+            }
+
+            transaction
+                .read()
+                .unwrap()
                 .get(&id)
-                .and_then(|entity| {
-                    registered_bodies_map
-                        .get(entity)
-                        .cloned()
-                        .map(rhai::Dynamic::from)
-                })
-            .unwrap_or(rhai::Dynamic::UNIT)
+                .cloned()
+                .map(rhai::Dynamic::from)
+                .unwrap_or(rhai::Dynamic::UNIT)
         });
+    }
+
+    {
+        // every body rather than just the script's, so that the totals match
+        // the Diagnostics panel, which has already worked them out if it's open
+        let bodies = match diagnostics_res.current {
+            Some(_) => Vec::new(),
+            None => {
+                let body_softenings = body_softenings
+                    .iter()
+                    .map(|(id, body_softening)| (id.0, body_softening.0))
+                    .collect::<BTreeMap<_, _>>();
+                let registered = registered_bodies.iter_mut().map(|(_, body, id)| {
+                    let body_softening = body_softenings.get(&id.0).copied();
+                    (body.clone(), body_softening.unwrap_or(softening.0))
+                });
+                let others = other_bodies.iter().map(|(body, body_softening)| {
+                    (
+                        body.clone(),
+                        BodySoftening::resolve(body_softening, &softening),
+                    )
+                });
+                registered.chain(others).collect::<Vec<_>>()
+            }
+        };
+        let g = g.0;
+
+        // the potential energy is a pass over every pair of bodies, so it's
+        // only worked out once a frame, on the first call
+        let frame_diagnostics = Arc::new(RwLock::new(diagnostics_res.current));
+        let diagnostics = Arc::new(move || {
+            *frame_diagnostics
+                .write()
+                .unwrap()
+                .get_or_insert_with(|| Diagnostics::compute(&bodies, g))
+        });
+
+        let diagnostics_ref = diagnostics.clone();
+        rhai.engine.register_fn("kinetic_energy", move || diagnostics_ref().kinetic_energy);
+        let diagnostics_ref = diagnostics.clone();
+        rhai.engine.register_fn("potential_energy", move || diagnostics_ref().potential_energy);
+        let diagnostics_ref = diagnostics.clone();
+        rhai.engine.register_fn("total_energy", move || diagnostics_ref().total_energy());
+        let diagnostics_ref = diagnostics.clone();
+        rhai.engine.register_fn("total_momentum", move || diagnostics_ref().momentum);
+        let diagnostics_ref = diagnostics.clone();
+        rhai.engine.register_fn("angular_momentum", move || diagnostics_ref().angular_momentum);
+        rhai.engine.register_fn("center_of_mass", move || diagnostics().center_of_mass);
+    }
+
+    let dt = Arc::new(dt.0);
+    rhai.engine.register_fn("DT", move || *dt.clone());
 
-        let dt = Arc::new(dt.0);
-        rhai.engine.register_fn("DT", move || *dt.clone());
+    let paused = Arc::new(paused.0);
+    rhai.engine.register_fn("is_paused", move || *paused.clone());
 
-        let paused = Arc::new(paused.0);
-        rhai.engine.register_fn("is_paused", move || *paused.clone());
+    let time = sim_time.0;
+    rhai.graphs.write().unwrap().time = time;
+    rhai.engine.register_fn("time", move || time);
 
-        let ast = &rhai.last_code;
+    rhai.start_frame();
+    let events = std::mem::take(&mut *rhai.events.write().unwrap());
+    for event in events {
+        let hook_name = event.hook();
+        let hook = match rhai.scope.get_value::<rhai::FnPtr>(hook_name) {
+            Some(hook) => hook,
+            None => continue,
+        };
 
-        let res: Result<rhai::Dynamic, _> =
-            update_fn.call(&rhai.engine, ast, (existing_body_ids, existing_body_map));
+        let res = run_transaction(&rhai, &mut registered_bodies, |rhai| {
+            let ast = &rhai.last_code;
+            match event {
+                ScriptEvent::Collision { survivor, absorbed } => {
+                    hook.call(&rhai.engine, ast, (survivor, absorbed))
+                }
+                ScriptEvent::BodyAdded(id) | ScriptEvent::BodyDeleted(id) => {
+                    hook.call(&rhai.engine, ast, (id,))
+                }
+                ScriptEvent::Click(pos) => hook.call(&rhai.engine, ast, (pos,)),
+            }
+        });
         if let Err(e) = res {
-            *rhai.output.write().unwrap() = e.to_string();
+            *rhai.output.write().unwrap() =
+                format!("{}: {}", hook_name, rhai.limits.describe_error(&e));
+            rhai.scope.set_value(hook_name, ());
+            source.output = Some(rhai.output.clone());
+        }
+    }
+
+    let pressed = rhai.controls.write().unwrap().take_pressed();
+    for (name, callback) in pressed {
+        let res = run_transaction(&rhai, &mut registered_bodies, |rhai| {
+            callback.call(&rhai.engine, &rhai.last_code, ())
+        });
+        if let Err(e) = res {
+            *rhai.output.write().unwrap() =
+                format!("{}: {}", name, rhai.limits.describe_error(&e));
+            source.output = Some(rhai.output.clone());
+        }
+    }
+
+    if let Some(update_fn) = update_fn {
+        // built after the hooks have run so that update sees their changes
+        let existing_body_ids = existing_bodies
+            .read()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        let body_view = BodyView {
+            transaction: rhai.transaction.clone(),
+            existing_bodies: existing_bodies.clone(),
+        };
+
+        let res = run_transaction(&rhai, &mut registered_bodies, |rhai| {
+            update_fn.call(&rhai.engine, &rhai.last_code, (existing_body_ids, body_view))
+        });
+        if let Err(e) = res {
+            *rhai.output.write().unwrap() = rhai.limits.describe_error(&e);
             rhai.scope.set_value("update", ());
-            code_editor.output = Some(rhai.output.clone());
+            source.output = Some(rhai.output.clone());
+        }
+    }
+}
+
+/// Runs a script call with body updates applied to the transaction, then writes
+/// them all back at once, or throws them away if the call failed
+fn run_transaction(
+    rhai: &RhaiRes,
+    bodies: &mut Query<(Entity, &mut KinematicBody, &RhaiID)>,
+    call: impl FnOnce(&RhaiRes) -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>>,
+) -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>> {
+    rhai.transaction.write().unwrap().active = true;
+    let res = call(rhai);
+
+    let mut transaction = rhai.transaction.write().unwrap();
+    transaction.active = false;
+    match &res {
+        Ok(_) => {
+            let body_reader = rhai.existing_bodies.read().unwrap();
+            for (id, new_body) in transaction.commit() {
+                if let Some((_, mut body, _)) =
+                    body_reader.get(&id).and_then(|e| bodies.get_mut(*e).ok())
+                {
+                    *body = new_body;
+                }
+            }
         }
+        Err(_) => transaction.rollback(),
     }
+
+    res
 }
@@ -0,0 +1,101 @@
+pub enum ScriptControl {
+    Slider { value: f32, min: f32, max: f32 },
+    Checkbox(bool),
+    Button(rhai::FnPtr),
+}
+
+/// Controls declared by the running script, in the order they were declared
+#[derive(Default)]
+pub struct ScriptControls {
+    pub controls: Vec<(String, ScriptControl)>,
+    /// Controls from before the script was last run, so that running it again
+    /// keeps the values the user picked
+    previous: Vec<(String, ScriptControl)>,
+    /// Buttons clicked since the script last updated
+    pub pressed: Vec<String>,
+}
+
+fn find<'a>(controls: &'a [(String, ScriptControl)], name: &str) -> Option<&'a ScriptControl> {
+    controls
+        .iter()
+        .find(|(control_name, _)| control_name == name)
+        .map(|(_, control)| control)
+}
+
+impl ScriptControls {
+    pub fn begin_run(&mut self) {
+        self.previous = std::mem::take(&mut self.controls);
+        self.pressed.clear();
+    }
+
+    fn set(&mut self, name: &str, control: ScriptControl) {
+        let index = self
+            .controls
+            .iter()
+            .position(|(control_name, _)| control_name == name);
+        match index {
+            Some(i) => self.controls[i].1 = control,
+            None => self.controls.push((name.to_string(), control)),
+        }
+    }
+
+    /// Declares a slider, or returns its current value if it already exists
+    pub fn slider(&mut self, name: &str, min: f32, max: f32, initial: f32) -> f32 {
+        if let Some(ScriptControl::Slider { value, .. }) = find(&self.controls, name) {
+            return *value;
+        }
+
+        let (min, max) = if min <= max { (min, max) } else { (max, min) };
+        let value = match find(&self.previous, name) {
+            Some(ScriptControl::Slider { value, .. }) => value.max(min).min(max),
+            _ => initial,
+        };
+
+        self.set(name, ScriptControl::Slider { value, min, max });
+        value
+    }
+
+    /// Declares a checkbox, or returns its current value if it already exists
+    pub fn checkbox(&mut self, name: &str, initial: bool) -> bool {
+        if let Some(ScriptControl::Checkbox(checked)) = find(&self.controls, name) {
+            return *checked;
+        }
+
+        let checked = match find(&self.previous, name) {
+            Some(ScriptControl::Checkbox(checked)) => *checked,
+            _ => initial,
+        };
+
+        self.set(name, ScriptControl::Checkbox(checked));
+        checked
+    }
+
+    pub fn button(&mut self, name: &str, callback: rhai::FnPtr) {
+        self.set(name, ScriptControl::Button(callback));
+    }
+
+    pub fn value(&self, name: &str) -> rhai::Dynamic {
+        match find(&self.controls, name) {
+            Some(ScriptControl::Slider { value, .. }) => rhai::Dynamic::from(*value),
+            Some(ScriptControl::Checkbox(checked)) => rhai::Dynamic::from(*checked),
+            _ => rhai::Dynamic::UNIT,
+        }
+    }
+
+    pub fn has_buttons(&self) -> bool {
+        self.controls
+            .iter()
+            .any(|(_, control)| matches!(control, ScriptControl::Button(_)))
+    }
+
+    /// The callbacks of the buttons clicked since the last call
+    pub fn take_pressed(&mut self) -> Vec<(String, rhai::FnPtr)> {
+        std::mem::take(&mut self.pressed)
+            .into_iter()
+            .filter_map(|name| match find(&self.controls, &name) {
+                Some(ScriptControl::Button(callback)) => Some((name, callback.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,298 @@
+use glam::Vec2;
+
+use crate::color::{Color, WHITE};
+
+#[cfg(feature = "gui")]
+use egui_macroquad::macroquad::prelude::{
+    draw_circle, draw_circle_lines, draw_line, draw_rectangle, draw_rectangle_lines, draw_text_ex,
+    draw_triangle, set_camera, set_default_camera, Camera2D, TextParams,
+};
+
+#[cfg(feature = "gui")]
+// glyphs are rasterized at this size and then scaled to the requested size
+const TEXT_RASTER_SIZE: u16 = 64;
+
+pub enum Shape {
+    Line {
+        start: Vec2,
+        end: Vec2,
+        thickness: f32,
+    },
+    Circle {
+        center: Vec2,
+        radius: f32,
+        filled: bool,
+        thickness: f32,
+    },
+    Rect {
+        pos: Vec2,
+        size: Vec2,
+        filled: bool,
+        thickness: f32,
+    },
+    Polyline {
+        points: Vec<Vec2>,
+        thickness: f32,
+        closed: bool,
+    },
+    Polygon {
+        points: Vec<Vec2>,
+    },
+    Arrow {
+        start: Vec2,
+        end: Vec2,
+        thickness: f32,
+        head_size: f32,
+    },
+    Text {
+        pos: Vec2,
+        text: String,
+        size: f32,
+    },
+}
+
+/// A shape requested by a script's `draw` call
+pub struct Drawing {
+    pub shape: Shape,
+    pub color: Color,
+    /// Screen-space drawings are positioned in pixels from the top left of
+    /// the window instead of following the camera
+    pub screen_space: bool,
+}
+
+fn get_f32(params: &rhai::Map, key: &str) -> Option<f32> {
+    let value = params.get(key)?;
+    value
+        .clone()
+        .try_cast::<f32>()
+        .or_else(|| value.clone().try_cast::<i64>().map(|i| i as f32))
+}
+
+fn get_vec(params: &rhai::Map, key: &str) -> Option<Vec2> {
+    params.get(key)?.clone().try_cast::<Vec2>()
+}
+
+fn get_bool(params: &rhai::Map, key: &str) -> Option<bool> {
+    params.get(key)?.clone().try_cast::<bool>()
+}
+
+fn get_points(params: &rhai::Map) -> Result<Vec<Vec2>, String> {
+    let points = params
+        .get("points")
+        .and_then(|points| points.clone().try_cast::<rhai::Array>())
+        .ok_or_else(|| "expected points to be an array of vectors".to_string())?;
+
+    points
+        .into_iter()
+        .map(|point| {
+            point
+                .try_cast::<Vec2>()
+                .ok_or_else(|| "expected points to be an array of vectors".to_string())
+        })
+        .collect()
+}
+
+/// A named group of drawings that stays on screen until the script clears it
+pub struct DrawLayer {
+    pub visible: bool,
+    pub drawings: Vec<Drawing>,
+}
+
+impl Default for DrawLayer {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            drawings: Vec::new(),
+        }
+    }
+}
+
+impl Drawing {
+    pub fn from_rhai(params: &rhai::Map) -> Result<Self, String> {
+        let shape_type = params
+            .get("type")
+            .and_then(|shape_type| shape_type.clone().try_cast::<String>())
+            .ok_or_else(|| "missing a type, such as \"line\" or \"circle\"".to_string())?;
+
+        let thickness = get_f32(params, "thickness").unwrap_or(1.0);
+        let filled = get_bool(params, "filled").unwrap_or(true);
+
+        let shape = match shape_type.as_str() {
+            "line" => Shape::Line {
+                start: get_vec(params, "start").unwrap_or(Vec2::ZERO),
+                end: get_vec(params, "end").unwrap_or(Vec2::ZERO),
+                thickness,
+            },
+            "circle" => Shape::Circle {
+                center: get_vec(params, "center").unwrap_or(Vec2::ZERO),
+                radius: get_f32(params, "radius").unwrap_or(1.0),
+                filled,
+                thickness,
+            },
+            "rect" => Shape::Rect {
+                pos: get_vec(params, "pos").unwrap_or(Vec2::ZERO),
+                size: get_vec(params, "size").unwrap_or(Vec2::ZERO),
+                filled,
+                thickness,
+            },
+            "polyline" => Shape::Polyline {
+                points: get_points(params)?,
+                thickness,
+                closed: get_bool(params, "closed").unwrap_or(false),
+            },
+            "polygon" if filled => Shape::Polygon {
+                points: get_points(params)?,
+            },
+            "polygon" => Shape::Polyline {
+                points: get_points(params)?,
+                thickness,
+                closed: true,
+            },
+            "arrow" => Shape::Arrow {
+                start: get_vec(params, "start").unwrap_or(Vec2::ZERO),
+                end: get_vec(params, "end").unwrap_or(Vec2::ZERO),
+                thickness,
+                head_size: get_f32(params, "head_size").unwrap_or(thickness * 4.0),
+            },
+            "text" => Shape::Text {
+                pos: get_vec(params, "pos").unwrap_or(Vec2::ZERO),
+                text: params
+                    .get("text")
+                    .map(|text| text.to_string())
+                    .unwrap_or_default(),
+                size: get_f32(params, "size").unwrap_or(20.0),
+            },
+            other => {
+                return Err(format!(
+                    "unknown shape type \"{}\", expected one of: line, circle, rect, polyline, polygon, arrow, text",
+                    other
+                ))
+            }
+        };
+
+        let color = params
+            .get("color")
+            .and_then(|color| color.clone().try_cast::<Color>())
+            .unwrap_or(WHITE);
+
+        Ok(Drawing {
+            shape,
+            color,
+            screen_space: get_bool(params, "screen").unwrap_or(false),
+        })
+    }
+}
+
+#[cfg(feature = "gui")]
+impl Drawing {
+    pub fn draw(&self, camera: &Camera2D) {
+        if self.screen_space {
+            set_default_camera();
+        }
+
+        let color = self.color.into();
+        match &self.shape {
+            Shape::Line {
+                start,
+                end,
+                thickness,
+            } => draw_line(start.x, start.y, end.x, end.y, *thickness, color),
+            Shape::Circle {
+                center,
+                radius,
+                filled: true,
+                ..
+            } => draw_circle(center.x, center.y, *radius, color),
+            Shape::Circle {
+                center,
+                radius,
+                thickness,
+                ..
+            } => draw_circle_lines(center.x, center.y, *radius, *thickness, color),
+            Shape::Rect {
+                pos,
+                size,
+                filled: true,
+                ..
+            } => draw_rectangle(pos.x, pos.y, size.x, size.y, color),
+            Shape::Rect {
+                pos,
+                size,
+                thickness,
+                ..
+            } => draw_rectangle_lines(pos.x, pos.y, size.x, size.y, *thickness, color),
+            Shape::Polyline {
+                points,
+                thickness,
+                closed,
+            } => {
+                for pair in points.windows(2) {
+                    draw_line(
+                        pair[0].x, pair[0].y, pair[1].x, pair[1].y, *thickness, color,
+                    );
+                }
+                if let (true, Some(first), Some(last)) = (*closed, points.first(), points.last()) {
+                    draw_line(last.x, last.y, first.x, first.y, *thickness, color);
+                }
+            }
+            Shape::Polygon { points } => {
+                // fan triangulation, which is only right for convex polygons
+                if let Some((first, rest)) = points.split_first() {
+                    for pair in rest.windows(2) {
+                        draw_triangle(*first, pair[0], pair[1], color);
+                    }
+                }
+            }
+            Shape::Arrow {
+                start,
+                end,
+                thickness,
+                head_size,
+            } => {
+                let dir = (*end - *start).normalize_or_zero();
+                let normal = dir.perp();
+                let head_base = *end - dir * *head_size;
+
+                draw_line(
+                    start.x,
+                    start.y,
+                    head_base.x,
+                    head_base.y,
+                    *thickness,
+                    color,
+                );
+                draw_triangle(
+                    *end,
+                    head_base + normal * *head_size / 2.0,
+                    head_base - normal * *head_size / 2.0,
+                    color,
+                );
+            }
+            Shape::Text { pos, text, size } => {
+                // the world camera points y up, which would draw text upside down
+                let flip = if !self.screen_space && camera.zoom.y > 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+
+                draw_text_ex(
+                    text,
+                    pos.x,
+                    pos.y,
+                    TextParams {
+                        font_size: TEXT_RASTER_SIZE,
+                        font_scale: flip * size / TEXT_RASTER_SIZE as f32,
+                        font_scale_aspect: flip,
+                        color,
+                        ..TextParams::default()
+                    },
+                );
+            }
+        }
+
+        if self.screen_space {
+            set_camera(camera);
+        }
+    }
+}
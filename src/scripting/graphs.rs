@@ -0,0 +1,129 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::color::{Color, WHITE};
+
+pub const DEFAULT_WINDOW: &str = "Graphs";
+
+#[derive(Clone, Debug)]
+pub struct Graph {
+    /// `[x, y]` pairs, where x is the simulation time unless the graph is XY
+    pub points: VecDeque<[f32; 2]>,
+    pub max_points: usize,
+    pub label: String,
+    pub color: [u8; 3],
+    pub window: String,
+    pub xy: bool,
+}
+
+impl Graph {
+    pub fn new(name: &str, max_points: usize, r: u8, g: u8, b: u8) -> Graph {
+        Graph {
+            points: VecDeque::new(),
+            max_points,
+            label: name.to_string(),
+            color: [r, g, b],
+            window: DEFAULT_WINDOW.to_string(),
+            xy: false,
+        }
+    }
+
+    /// Reads the options map given to `new_graph`
+    pub fn from_rhai(name: &str, options: &rhai::Map) -> Graph {
+        let max_points = options
+            .get("max_points")
+            .and_then(|max_points| max_points.as_int().ok())
+            .unwrap_or(1000);
+        let color = options
+            .get("color")
+            .and_then(|color| color.clone().try_cast::<Color>())
+            .unwrap_or(WHITE);
+        let [r, g, b, _] = color.into();
+
+        let mut graph = Graph::new(name, max_points.max(1) as usize, r, g, b);
+        if let Some(window) = options.get("window") {
+            graph.window = window.to_string();
+        }
+        graph.xy = options
+            .get("xy")
+            .and_then(|xy| xy.as_bool().ok())
+            .unwrap_or(false);
+
+        graph
+    }
+
+    pub fn push(&mut self, point: [f32; 2]) {
+        self.points.push_back(point);
+        while self.points.len() > self.max_points {
+            self.points.pop_front();
+        }
+    }
+
+    pub fn to_csv(&self, x_label: &str, y_label: &str) -> String {
+        let header = |label: &str, fallback: &str| {
+            if label.is_empty() {
+                fallback.to_string()
+            } else {
+                label.replace(',', " ")
+            }
+        };
+
+        // time series in one window share a y label, so use their own names
+        let y_header = if self.xy {
+            header(y_label, "y")
+        } else {
+            header(&self.label, "y")
+        };
+
+        let mut csv = format!("{},{}\n", header(x_label, "x"), y_header);
+        for [x, y] in &self.points {
+            csv.push_str(&format!("{},{}\n", x, y));
+        }
+        csv
+    }
+}
+
+/// A window holding one or more graphs
+#[derive(Default)]
+pub struct PlotWindow {
+    pub x_label: String,
+    pub y_label: String,
+    pub log_x: bool,
+    pub log_y: bool,
+}
+
+#[derive(Default)]
+pub struct Graphs {
+    pub graphs: BTreeMap<String, Graph>,
+    pub windows: BTreeMap<String, PlotWindow>,
+    /// The x of points added without one
+    pub time: f32,
+}
+
+impl Graphs {
+    pub fn insert(&mut self, graph: Graph) {
+        let window = self.windows.entry(graph.window.clone()).or_default();
+        if !graph.xy && window.x_label.is_empty() {
+            window.x_label = "time".to_string();
+        }
+
+        self.graphs.insert(graph.label.clone(), graph);
+    }
+
+    pub fn add_point(&mut self, name: &str, y: f32) {
+        let time = self.time;
+        if let Some(graph) = self.graphs.get_mut(name) {
+            graph.push([time, y]);
+        }
+    }
+
+    pub fn add_xy_point(&mut self, name: &str, x: f32, y: f32) {
+        if let Some(graph) = self.graphs.get_mut(name) {
+            graph.push([x, y]);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.graphs.clear();
+        self.windows.clear();
+    }
+}
@@ -0,0 +1,185 @@
+use rhai::{Dynamic, Engine, EvalAltResult};
+
+use std::sync::{Arc, RwLock};
+
+// checking the clock on every operation would slow scripts down noticeably
+const OPS_PER_CLOCK_CHECK: u64 = 256;
+
+/// Limits that stop a runaway script instead of letting it freeze the app.
+/// Setting any of them to 0 turns it off.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ScriptLimits {
+    /// Operations in a single run, `update` call or hook call
+    pub max_operations: u64,
+    pub max_call_depth: usize,
+    /// How deeply expressions can nest, since parsing them recurses and a
+    /// deep enough one would overflow the stack
+    pub max_expr_depth: usize,
+    pub max_string_size: usize,
+    /// Also applies to object maps
+    pub max_array_size: usize,
+    /// Milliseconds that all of the script calls in one frame can take together
+    pub frame_budget_ms: f32,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: 10_000_000,
+            max_call_depth: 64,
+            max_expr_depth: 64,
+            max_string_size: 1_000_000,
+            max_array_size: 1_000_000,
+            frame_budget_ms: 250.0,
+        }
+    }
+}
+
+impl ScriptLimits {
+    pub fn apply(&self, engine: &mut Engine) {
+        engine.set_max_operations(self.max_operations);
+        // unlike the others, rhai's call limit of 0 allows no calls at all
+        let max_call_levels = match self.max_call_depth {
+            0 => usize::MAX,
+            depth => depth,
+        };
+        engine.set_max_call_levels(max_call_levels);
+        engine.set_max_expr_depths(self.max_expr_depth, self.max_expr_depth);
+        engine.set_max_string_size(self.max_string_size);
+        engine.set_max_array_size(self.max_array_size);
+        engine.set_max_map_size(self.max_array_size);
+    }
+
+    /// Explains which limit stopped the script, or gives Rhai's own message
+    /// for any other error
+    pub fn describe_error(&self, e: &EvalAltResult) -> String {
+        let mut inner = e;
+        while let EvalAltResult::ErrorInFunctionCall(_, _, err, _) = inner {
+            inner = err;
+        }
+
+        let reason = match inner {
+            EvalAltResult::ErrorTooManyOperations(_) => format!(
+                "it ran more than {} operations in one call",
+                self.max_operations
+            ),
+            EvalAltResult::ErrorStackOverflow(_) => format!(
+                "functions were nested more than {} calls deep",
+                self.max_call_depth
+            ),
+            EvalAltResult::ErrorDataTooLarge(kind, _) => {
+                format!("{} grew past the size limit", kind.to_lowercase())
+            }
+            EvalAltResult::ErrorTerminated(..) => format!(
+                "scripts ran for more than the {} ms frame budget",
+                self.frame_budget_ms
+            ),
+            _ => return e.to_string(),
+        };
+
+        let position = inner.position();
+        if position.is_none() {
+            format!("Script stopped: {}", reason)
+        } else {
+            format!("Script stopped at {}: {}", position, reason)
+        }
+    }
+}
+
+/// Seconds since the epoch, like miniquad's clock, without needing a window
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now() -> f64 {
+    js_sys::Date::now() / 1000.0
+}
+
+/// The time after which script calls in the current frame are stopped
+#[derive(Clone)]
+pub struct FrameDeadline(Arc<RwLock<f64>>);
+
+impl Default for FrameDeadline {
+    fn default() -> Self {
+        Self(Arc::new(RwLock::new(f64::INFINITY)))
+    }
+}
+
+impl FrameDeadline {
+    pub fn start_frame(&self, budget_ms: f32) {
+        *self.0.write().unwrap() = if budget_ms > 0.0 {
+            now() + budget_ms as f64 / 1000.0
+        } else {
+            f64::INFINITY
+        };
+    }
+
+    /// Makes the engine stop any script that is still running after the deadline
+    pub fn watch(&self, engine: &mut Engine) {
+        let deadline = self.0.clone();
+        engine.on_progress(move |ops| {
+            if ops % OPS_PER_CLOCK_CHECK == 0 && now() > *deadline.read().unwrap() {
+                Some(Dynamic::UNIT)
+            } else {
+                None
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(limits: ScriptLimits, script: &str) -> Result<i64, Box<EvalAltResult>> {
+        let mut engine = Engine::new();
+        limits.apply(&mut engine);
+        engine.eval::<i64>(script)
+    }
+
+    #[test]
+    fn zero_call_depth_allows_calls() {
+        let limits = ScriptLimits {
+            max_call_depth: 0,
+            ..ScriptLimits::default()
+        };
+        let script = "fn add_one(x) { x + 1 } fn add_two(x) { add_one(add_one(x)) } add_two(1)";
+        assert_eq!(run(limits, script).unwrap(), 3);
+    }
+
+    #[test]
+    fn zero_turns_every_limit_off() {
+        let limits = ScriptLimits {
+            max_operations: 0,
+            max_call_depth: 0,
+            max_expr_depth: 0,
+            max_string_size: 0,
+            max_array_size: 0,
+            frame_budget_ms: 0.0,
+        };
+        let script = "
+            fn depth(n) { if n == 0 { 0 } else { 1 + depth(n - 1) } }
+            let s = \"abc\";
+            let a = [1, 2, 3];
+            let m = #{ x: 1 };
+            depth(10) + s.len() + a.len() + m.len()
+        ";
+        assert_eq!(run(limits, script).unwrap(), 17);
+    }
+
+    #[test]
+    fn call_depth_stops_deep_recursion() {
+        let limits = ScriptLimits {
+            max_call_depth: 8,
+            ..ScriptLimits::default()
+        };
+        let script = "fn depth(n) { if n == 0 { 0 } else { 1 + depth(n - 1) } } depth(20)";
+        let e = run(limits, script).unwrap_err();
+        assert!(limits.describe_error(&e).contains("8 calls deep"));
+    }
+}
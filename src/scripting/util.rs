@@ -1,5 +1,5 @@
 use crate::physics::KinematicBody;
-use egui_macroquad::macroquad::prelude::*;
+use glam::Vec2;
 
 macro_rules! gen_accessors {
     ($get_ident:ident, $set_ident:ident, $field:ident, $ty:ty) => {
@@ -55,6 +55,42 @@ impl KinematicBody {
         }
     }
 
+    /// Applies the `set_{field}` and `add_{field}` entries of an `update_body` map
+    pub fn apply_rhai_params(&mut self, params: &rhai::Map) {
+        // lets set_mass(5) work the same as set_mass(5.0)
+        let param = |key: &str| {
+            params.get(key).map(|dynamic| match dynamic.as_int() {
+                Ok(int) => rhai::Dynamic::from(int as f32),
+                Err(_) => dynamic.clone(),
+            })
+        };
+
+        macro_rules! generate_set_add {
+            ($field:ident, $str:expr, $ty:ty) => {
+                let set =
+                    param(concat!("set_", $str)).and_then(|dynamic| dynamic.try_cast::<$ty>());
+
+                if let Some(field) = set {
+                    self.$field = field;
+                }
+
+                let add =
+                    param(concat!("add_", $str)).and_then(|dynamic| dynamic.try_cast::<$ty>());
+
+                if let Some(field) = add {
+                    self.$field += field;
+                }
+            };
+        }
+
+        generate_set_add!(pos, "pos", Vec2);
+        generate_set_add!(vel, "vel", Vec2);
+        generate_set_add!(accel, "accel", Vec2);
+        generate_set_add!(force, "force", Vec2);
+        generate_set_add!(mass, "mass", f32);
+        generate_set_add!(radius, "radius", f32);
+    }
+
     gen_accessors!(get_pos, set_pos, pos, Vec2);
     gen_accessors!(get_vel, set_vel, vel, Vec2);
     gen_accessors!(get_force, set_force, force, Vec2);
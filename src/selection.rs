@@ -0,0 +1,8 @@
+use bevy_ecs::prelude::*;
+
+/// The body shown in the inspector
+pub struct InspectedEntity(pub Option<Entity>);
+
+/// The body the camera follows. Only inserted by the app, so systems that
+/// clear it take it as an `Option`.
+pub struct FollowBody(pub Option<Entity>);
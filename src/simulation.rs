@@ -0,0 +1,219 @@
+use bevy_ecs::prelude::*;
+use slotmap::DefaultKey;
+
+use crate::physics::{self, KinematicBody, Paused, Preview, Steps};
+use crate::scene::SceneIO;
+use crate::scripting::{RhaiID, RhaiRes, ScriptSource};
+use crate::selection::InspectedEntity;
+
+/// The physics and scripting half of the app, which doesn't need a window
+pub struct Simulation {
+    pub world: World,
+    physics_schedule: Schedule,
+    sample_schedule: Schedule,
+    scripting_schedule: Schedule,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        let world = {
+            let mut world = World::new();
+
+            world.insert_resource(physics::DT(1.0));
+            world.insert_resource(physics::G(100.0));
+            world.insert_resource(physics::SimTime(0.0));
+            world.insert_resource(Steps(1));
+            world.insert_resource(physics::Softening(0.0));
+            world.insert_resource(physics::CollisionMode::default());
+            world.insert_resource(physics::Restitution(0.5));
+            world.insert_resource(physics::Fragmentation::default());
+
+            world.insert_resource(Paused(false));
+            world.insert_resource(physics::PhysicsToggles {
+                collisions: true,
+                integration: true,
+            });
+            world.insert_resource(physics::Integrator::default());
+            world.insert_resource(physics::ExternalForces::default());
+            world.insert_resource(physics::GravitySolver::default());
+            world.insert_resource(physics::AdaptiveDT::default());
+
+            world.insert_resource(crate::trails::RelativeTrails(None));
+            world.insert_resource(crate::trails::DrawTrails(true));
+            world.insert_resource(crate::force_lines::DrawForceLines(false));
+            world.insert_resource(InspectedEntity(None));
+            world.insert_resource(crate::diagnostics::DiagnosticsRes::default());
+            world.insert_resource(crate::history::History::default());
+
+            world.insert_resource(ScriptSource::default());
+            world.insert_resource(RhaiRes::default());
+            world.insert_resource(SceneIO::default());
+
+            world
+        };
+
+        let physics_schedule = {
+            let mut physics_schedule = Schedule::default();
+
+            physics_schedule.add_stage(
+                "physics",
+                SystemStage::single_threaded()
+                    .with_system(physics::gravity_sys.system().label("gravity"))
+                    .with_system(
+                        physics::collision_sys
+                            .system()
+                            .label("collision")
+                            .after("gravity"),
+                    )
+                    .with_system(
+                        physics::bounce_collision_sys
+                            .system()
+                            .label("collision")
+                            .after("gravity"),
+                    )
+                    .with_system(
+                        physics::adaptive_dt_sys
+                            .system()
+                            .label("adaptive_dt")
+                            .after("collision"),
+                    )
+                    .with_system(
+                        physics::integration_sys
+                            .system()
+                            .label("integration")
+                            .after("adaptive_dt"),
+                    )
+                    .with_system(physics::sim_time_sys.system().after("integration")),
+            );
+
+            physics_schedule
+        };
+
+        // runs once per frame after all of the physics substeps
+        let sample_schedule = {
+            let mut sample_schedule = Schedule::default();
+
+            sample_schedule.add_stage(
+                "sample",
+                SystemStage::single_threaded()
+                    .with_system(crate::trails::trail_sys.system())
+                    .with_system(crate::trails::clear_trails_sys.system())
+                    .with_system(crate::force_lines::force_line_sys.system())
+                    .with_system(crate::diagnostics::diagnostics_sys.system())
+                    .with_system(crate::preview::trajectory_prediction_sys.system()),
+            );
+
+            // after sampling so that snapshots include the latest trail points
+            sample_schedule.add_stage(
+                "history",
+                SystemStage::single_threaded()
+                    .with_system(crate::history::history_sys.exclusive_system()),
+            );
+
+            sample_schedule
+        };
+
+        let scripting_schedule = {
+            let mut scripting_schedule = Schedule::default();
+
+            scripting_schedule.add_stage(
+                "scripting",
+                SystemStage::single_threaded()
+                    .with_system(crate::scripting::run_code_sys.system().label("run"))
+                    .with_system(
+                        crate::scripting::run_rhai_commands_sys
+                            .system()
+                            .label("commands")
+                            .after("run"),
+                    ),
+            );
+
+            // separate stage so that bodies deleted by commands are already
+            // despawned when update runs
+            scripting_schedule.add_stage(
+                "update",
+                SystemStage::single_threaded()
+                    .with_system(crate::scripting::run_script_update_sys.system()),
+            );
+
+            scripting_schedule.add_stage(
+                "deletions",
+                SystemStage::single_threaded()
+                    .with_system(crate::scripting::apply_deletions_sys.system()),
+            );
+
+            // after the scripting stage so that save_scene and load_scene
+            // calls are handled the same frame
+            scripting_schedule.add_stage(
+                "scene",
+                SystemStage::single_threaded()
+                    .with_system(crate::scene::scene_sys.exclusive_system()),
+            );
+
+            scripting_schedule
+        };
+
+        Self {
+            world,
+            physics_schedule,
+            sample_schedule,
+            scripting_schedule,
+        }
+    }
+}
+
+impl Simulation {
+    /// Runs `Steps` physics substeps, then samples trails and force lines once
+    pub fn step(&mut self) {
+        // once per frame, since script forces only last until the first substep
+        let bodies = self
+            .world
+            .query_filtered::<&KinematicBody, Without<Preview>>()
+            .iter(&self.world)
+            .cloned()
+            .collect::<Vec<_>>();
+        self.world
+            .insert_resource(physics::ExternalForces::detect(bodies));
+
+        let steps = self.world.get_resource::<Steps>().unwrap().0;
+        for _ in 0..steps.max(1) {
+            self.physics_schedule.run(&mut self.world);
+        }
+        self.sample_schedule.run(&mut self.world);
+    }
+
+    /// Applies queued script commands and calls the script's `update`
+    pub fn run_scripting(&mut self) {
+        self.scripting_schedule.run(&mut self.world);
+    }
+
+    /// Runs a scene script, the same way as pressing Run in the editor
+    pub fn load_script(&mut self, code: &str) {
+        {
+            let mut source = self.world.get_resource_mut::<ScriptSource>().unwrap();
+            *source.code.write().unwrap() = code.to_string();
+            source.should_run = true;
+        }
+
+        self.run_scripting();
+    }
+
+    /// Takes everything printed by scripts, including errors, since the last call
+    pub fn take_output(&self) -> String {
+        let rhai = self.world.get_resource::<RhaiRes>().unwrap();
+        let mut output = rhai.output.write().unwrap();
+        std::mem::take(&mut *output)
+    }
+
+    pub fn time(&self) -> f32 {
+        self.world.get_resource::<physics::SimTime>().unwrap().0
+    }
+
+    pub fn bodies(&mut self) -> Vec<(Option<DefaultKey>, KinematicBody)> {
+        self.world
+            .query_filtered::<(&KinematicBody, Option<&RhaiID>), Without<Preview>>()
+            .iter(&self.world)
+            .map(|(body, id)| (id.map(|id| id.0), body.clone()))
+            .collect()
+    }
+}
@@ -1,16 +1,20 @@
 use std::collections::VecDeque;
 
 use bevy_ecs::prelude::*;
-use egui_macroquad::macroquad::prelude::*;
+use glam::Vec2;
+
+#[cfg(feature = "gui")]
+use egui_macroquad::macroquad::prelude::{draw_line, Color};
 
 use crate::{
     physics::{KinematicBody, Paused, Preview},
-    preview::{MultiPreview, PreviewTrailTick},
+    preview::{MultiPreview, PredictedScene, PreviewTrailTick},
 };
 
 pub struct DrawTrails(pub bool);
 pub struct RelativeTrails(pub Option<Entity>);
 
+#[derive(Clone)]
 pub struct Trail {
     pub points: VecDeque<Vec2>,
     pub max_len: usize,
@@ -91,27 +95,34 @@ pub fn clear_trails_sys(
 }
 
 pub fn preview_trail_sys(
-    mut query: Query<(&KinematicBody, Option<&mut Trail>, Entity), With<Preview>>,
+    mut query: Query<
+        (
+            &KinematicBody,
+            Option<&mut Trail>,
+            Option<&PredictedScene>,
+            Entity,
+        ),
+        With<Preview>,
+    >,
     mut commands: Commands,
     mut preview_trail_tick: ResMut<PreviewTrailTick>,
     multi_preview: Res<MultiPreview>,
-    relative_trails_body: Res<RelativeTrails>,
 ) {
-    if relative_trails_body.0.is_some() {
-        return;
-    }
-
     preview_trail_tick.current_tick += 1;
     preview_trail_tick.current_tick %= preview_trail_tick.tick_increment;
 
     if preview_trail_tick.current_tick == 0 {
-        for (body, trail, entity) in query.iter_mut() {
+        for (body, trail, scene, entity) in query.iter_mut() {
             if let Some(mut trail) = trail {
                 if trail.points.len() == trail.max_len {
                     continue;
                 }
 
-                trail.points.push_back(body.pos);
+                // relative to where the predicted scene has moved the frame body
+                let origin = scene
+                    .map(PredictedScene::frame_origin)
+                    .unwrap_or(Vec2::ZERO);
+                trail.points.push_back(body.pos - origin);
                 while trail.points.len() > trail.max_len {
                     trail.points.pop_front();
                 }
@@ -124,6 +135,7 @@ pub fn preview_trail_sys(
     }
 }
 
+#[cfg(feature = "gui")]
 pub fn draw_trail_sys(
     query: Query<(&KinematicBody, &Trail, Option<&Preview>)>,
     draw_trails: Res<DrawTrails>,
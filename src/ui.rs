@@ -1,8 +1,10 @@
 pub mod body_creation;
 pub mod code_editor;
+pub mod diagnostics;
 pub mod graphs;
 pub mod input_state;
 pub mod inspect;
+pub mod script_controls;
 pub mod top_panel;
 
 use bevy_ecs::prelude::*;
@@ -21,6 +23,11 @@ pub fn handle_keybinds_sys(
 ) {
     use egui_macroquad::macroquad::prelude::*;
 
+    // typing in the code editor shouldn't trigger shortcuts
+    if egui_ctx.wants_keyboard_input() {
+        return;
+    }
+
     if is_key_pressed(KeyCode::Space) && !egui_ctx.is_pointer_over_area() {
         paused.0 = !paused.0;
     }
@@ -5,9 +5,11 @@ use macroquad::prelude::*;
 use super::input_state::MouseState;
 use crate::{
     camera::CameraRes,
-    physics::{KinematicBody, Preview},
+    orbit::periapsis_velocity,
+    physics::{KinematicBody, Preview, G},
     preview::MultiPreview,
-    scripting::{RhaiBody, RhaiRes, RhaiID},
+    scripting::{RhaiBody, RhaiRes, RhaiID, ScriptEvent},
+    selection::InspectedEntity,
 };
 
 #[derive(PartialEq, Debug)]
@@ -17,9 +19,41 @@ pub enum CreationState {
     Clicked { start_point: Vec2 },
 }
 
+/// How the velocity of a new body is chosen
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CreationMode {
+    /// From the mouse drag
+    #[default]
+    Drag,
+    /// In orbit around the nearest body
+    OrbitNearest,
+    /// In orbit around the inspected body
+    OrbitSelected,
+}
+
+impl CreationMode {
+    pub const ALL: [CreationMode; 3] = [
+        CreationMode::Drag,
+        CreationMode::OrbitNearest,
+        CreationMode::OrbitSelected,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CreationMode::Drag => "Drag",
+            CreationMode::OrbitNearest => "Orbit Nearest",
+            CreationMode::OrbitSelected => "Orbit Selected",
+        }
+    }
+}
+
 pub struct CreationData {
     pub radius: f32,
     pub mass: f32,
+    pub mode: CreationMode,
+    /// Orbits start at their periapsis, so 0 is a circle
+    pub eccentricity: f32,
+    pub clockwise: bool,
 }
 
 impl Default for CreationData {
@@ -27,6 +61,60 @@ impl Default for CreationData {
         Self {
             radius: 75.0,
             mass: 1.0,
+            mode: CreationMode::default(),
+            eccentricity: 0.0,
+            clockwise: false,
+        }
+    }
+}
+
+impl CreationData {
+    /// The body that a new body at `pos` would orbit, or `None` when the
+    /// velocity comes from dragging
+    pub fn primary<'a>(
+        &self,
+        pos: Vec2,
+        bodies: &'a Query<(Entity, &KinematicBody), Without<Preview>>,
+        inspected_entity: &InspectedEntity,
+    ) -> Option<&'a KinematicBody> {
+        match self.mode {
+            CreationMode::Drag => None,
+            CreationMode::OrbitNearest => bodies
+                .iter()
+                .min_by(|(_, a), (_, b)| {
+                    let a_dist = (a.pos - pos).length_squared();
+                    let b_dist = (b.pos - pos).length_squared();
+                    a_dist.total_cmp(&b_dist)
+                })
+                .map(|(_, body)| body),
+            CreationMode::OrbitSelected => inspected_entity
+                .0
+                .and_then(|entity| bodies.get(entity).ok())
+                .map(|(_, body)| body),
+        }
+    }
+
+    /// The velocity of a new body at `start_point`, moving with its primary
+    /// in orbit modes
+    pub fn velocity(
+        &self,
+        start_point: Vec2,
+        mouse_pos: Vec2,
+        primary: Option<&KinematicBody>,
+        g: f32,
+    ) -> Vec2 {
+        match primary {
+            Some(primary) => {
+                let mu = g * (primary.mass + self.mass);
+                primary.vel
+                    + periapsis_velocity(
+                        start_point - primary.pos,
+                        mu,
+                        self.eccentricity,
+                        self.clockwise,
+                    )
+            }
+            None => (start_point - mouse_pos) / 100.0,
         }
     }
 }
@@ -41,6 +129,9 @@ pub fn create_body_sys(
     camera_res: Res<CameraRes>,
     egui_ctx: Res<Context>,
     rhai: Res<RhaiRes>,
+    bodies: Query<(Entity, &KinematicBody), Without<Preview>>,
+    inspected_entity: Res<InspectedEntity>,
+    g: Res<G>,
 ) {
     match *creation_state {
         CreationState::Unstarted => {
@@ -65,6 +156,9 @@ pub fn create_body_sys(
             }
         }
         CreationState::Clicked { start_point } => {
+            let primary = creation_data.primary(start_point, &bodies, &inspected_entity);
+            let vel = creation_data.velocity(start_point, mouse_state.prev_position, primary, g.0);
+
             if is_mouse_button_released(MouseButton::Left) && !egui_ctx.is_pointer_over_area() {
                 if !multi_preview.0 {
                     preview_query.iter().for_each(|entity| {
@@ -78,21 +172,15 @@ pub fn create_body_sys(
                         pos: start_point,
                         mass: creation_data.mass,
                         radius: creation_data.radius,
-                        vel: (start_point - mouse_state.prev_position) / 100.0,
+                        vel,
                         ..Default::default()
                     })
                     .insert(RhaiBody)
                     .id();
 
-                // hacky way to get a unique key
-                let key = rhai
-                    .newly_added_bodies
-                    .write()
-                    .unwrap()
-                    .insert(rhai::Map::default());
-                rhai.existing_bodies.write().unwrap().insert(key, id);
-                rhai.newly_added_bodies.write().unwrap().remove(key);
+                let key = rhai.register_body(id);
                 commands.entity(id).insert(RhaiID(key));
+                rhai.push_event(ScriptEvent::BodyAdded(key));
 
                 *creation_state = CreationState::Initiated;
             } else {
@@ -109,12 +197,12 @@ pub fn create_body_sys(
                         .spawn()
                         .insert(KinematicBody {
                             pos: start_point,
-                            vel: (start_point - mouse_state.prev_position) / 100.0,
-                            mass: 1.0,
+                            vel,
+                            mass: creation_data.mass,
                             radius: creation_data.radius,
                             ..KinematicBody::default()
                         })
-                        .insert(Color::new(0.5, 0.7, 1.0, 0.8))
+                        .insert(crate::color::Color::new(0.5, 0.7, 1.0, 0.8))
                         .insert(Preview);
 
                     if !multi_preview.0 {
@@ -2,37 +2,206 @@ use bevy_ecs::prelude::*;
 use egui_macroquad::egui::{self, RichText, TextStyle};
 use egui_macroquad::macroquad::prelude::*;
 
-use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 
-use crate::scripting::RhaiRes;
+use crate::scripting::{RhaiRes, ScriptSource};
 
+#[cfg(not(target_arch = "wasm32"))]
+mod highlight;
+
+#[cfg(not(target_arch = "wasm32"))]
+// seconds between checks of a watched script file
+const WATCH_INTERVAL: f64 = 0.5;
+
+/// The scripting window. The script itself lives in `ScriptSource`.
 pub struct CodeEditor {
     pub shown: bool,
-    pub code: Arc<RwLock<String>>,
-    pub should_run: bool,
-    pub output: Option<Arc<RwLock<String>>>,
+    /// The script file opened and saved by the native editor
+    pub path: String,
+    /// Reload and re-run the script file whenever it changes on disk
+    pub watch: bool,
+    watched_modified: Option<SystemTime>,
+    last_watch_check: f64,
 }
 
 impl Default for CodeEditor {
     fn default() -> Self {
         Self {
             shown: false,
-            code: Arc::new(RwLock::new("".to_string())),
-            should_run: false,
-            output: None,
+            path: "script.rhai".to_string(),
+            watch: false,
+            watched_modified: None,
+            last_watch_check: 0.0,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn show_message(source: &mut ScriptSource, rhai: &RhaiRes, message: &str) {
+    rhai.output.write().unwrap().push_str(message);
+    source.output = Some(rhai.output.clone());
+}
+
+fn clear_scene(entities: &Query<Entity>, commands: &mut Commands, rhai: &RhaiRes) {
+    entities.iter().for_each(|e| commands.entity(e).despawn());
+    rhai.existing_bodies.write().unwrap().clear();
+    rhai.newly_added_bodies.write().unwrap().clear();
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Reloads the watched file and clears the scene to run it again, so that
+/// scripts can be written in another editor
+fn watch_file(
+    code_editor: &mut CodeEditor,
+    source: &mut ScriptSource,
+    entities: &Query<Entity>,
+    commands: &mut Commands,
+    rhai: &RhaiRes,
+) {
+    if !code_editor.watch || get_time() - code_editor.last_watch_check < WATCH_INTERVAL {
+        return;
+    }
+    code_editor.last_watch_check = get_time();
+
+    let modified = modified_time(&code_editor.path);
+    if modified.is_none() || modified == code_editor.watched_modified {
+        return;
+    }
+    code_editor.watched_modified = modified;
+
+    match std::fs::read_to_string(&code_editor.path) {
+        Ok(code) => {
+            *source.code.write().unwrap() = code;
+            clear_scene(entities, commands, rhai);
+            source.output = None;
+            source.should_run = true;
+        }
+        Err(e) => {
+            let message = format!("Couldn't read {}: {}\n", code_editor.path, e);
+            show_message(source, rhai, &message);
         }
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn file_ui(
+    ui: &mut egui::Ui,
+    code_editor: &mut CodeEditor,
+    source: &mut ScriptSource,
+    rhai: &RhaiRes,
+) {
+    ui.horizontal(|ui| {
+        ui.label("File");
+        ui.text_edit_singleline(&mut code_editor.path);
+
+        if ui.button("Open").clicked() {
+            match std::fs::read_to_string(&code_editor.path) {
+                Ok(code) => {
+                    *source.code.write().unwrap() = code;
+                    code_editor.watched_modified = modified_time(&code_editor.path);
+                }
+                Err(e) => {
+                    let message = format!("Couldn't open {}: {}\n", code_editor.path, e);
+                    show_message(source, rhai, &message);
+                }
+            }
+        }
+
+        if ui.button("Save").clicked() {
+            let code = source.code.read().unwrap().clone();
+            let message = match std::fs::write(&code_editor.path, code) {
+                Ok(()) => {
+                    // so that watching doesn't re-run the script just saved
+                    code_editor.watched_modified = modified_time(&code_editor.path);
+                    format!("Saved {}\n", code_editor.path)
+                }
+                Err(e) => format!("Couldn't save {}: {}\n", code_editor.path, e),
+            };
+            show_message(source, rhai, &message);
+        }
+
+        let watch = ui
+            .checkbox(&mut code_editor.watch, "Watch")
+            .on_hover_text("Clear the scene and run the file again whenever it changes");
+        if watch.changed() {
+            code_editor.watched_modified = None;
+        }
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// A text editor with line numbers and Rhai syntax highlighting. The wasm
+/// build uses the page's own editor instead.
+fn native_editor(ui: &mut egui::Ui, code: &mut String, error_line: Option<usize>) {
+    let font_id = TextStyle::Monospace.resolve(ui.style());
+
+    let mut layouter = |ui: &egui::Ui, text: &str, _wrap_width: f32| {
+        // no wrapping, so that rows line up with the line numbers
+        let job = highlight::highlight(text, font_id.clone(), error_line);
+        ui.fonts(|fonts| fonts.layout_job(job))
+    };
+
+    let line_count = code.split('\n').count();
+    let width = line_count.to_string().len();
+    let line_numbers = (1..=line_count)
+        .map(|line| format!("{:>width$}", line))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    egui::ScrollArea::both()
+        .id_source("code_editor_scroll")
+        .show(ui, |ui| {
+            ui.horizontal_top(|ui| {
+                ui.vertical(|ui| {
+                    // lines up with the text edit's inner margin
+                    ui.add_space(2.0);
+                    ui.label(
+                        RichText::new(line_numbers)
+                            .font(font_id.clone())
+                            .color(egui::Color32::GRAY),
+                    );
+                });
+                ui.add(
+                    egui::TextEdit::multiline(code)
+                        .code_editor()
+                        .desired_width(f32::INFINITY)
+                        .desired_rows(20)
+                        .lock_focus(true)
+                        .layouter(&mut layouter),
+                );
+            });
+        });
+}
+
 pub fn code_editor_sys(
     egui_ctx: Res<egui::Context>,
     mut code_editor: ResMut<CodeEditor>,
+    mut source: ResMut<ScriptSource>,
     entities: Query<Entity>,
     mut commands: Commands,
-    rhai: Res<RhaiRes>,
+    mut rhai: ResMut<RhaiRes>,
 ) {
+    #[cfg(not(target_arch = "wasm32"))]
+    watch_file(
+        &mut code_editor,
+        &mut source,
+        &entities,
+        &mut commands,
+        &rhai,
+    );
+
     let mut shown = code_editor.shown;
     let mut ace_shown = shown;
+    #[cfg(not(target_arch = "wasm32"))]
+    let error_line = source
+        .output
+        .as_ref()
+        .and_then(|output| highlight::error_line(&output.read().unwrap()));
 
     egui::Window::new("Scripting")
         .open(&mut shown)
@@ -45,28 +214,41 @@ pub fn code_editor_sys(
             let collapse_resp = 
                 egui::CollapsingHeader::new("Editor").default_open(true).show(ui, |ui| {
                     ui.set_max_height(screen_height() * 0.6);
+                    #[cfg(not(target_arch = "wasm32"))]
+                    file_ui(ui, &mut code_editor, &mut source, &rhai);
+
+                    let code_ref = source.code.clone();
+                    let mut code = code_ref.write().unwrap();
                     ui.horizontal(|ui| {
                         ui.vertical(|ui| {
                             ui.set_min_width(screen_width() * 0.4);
                             ui.set_min_height(screen_height() * 0.5);
-                            let padding = ui.style().spacing.window_margin.left_top();
-                            let pos = ui.clip_rect().min + padding;
-                            let y_offs = 32.0; // bc of the collapse title
+                            #[cfg(not(target_arch = "wasm32"))]
+                            native_editor(ui, &mut code, error_line);
+                            #[cfg(target_arch = "wasm32")]
+                            {
+                                let padding = ui.style().spacing.window_margin.left_top();
+                                let pos = ui.clip_rect().min + padding;
+                                let y_offs = 32.0; // bc of the collapse title
 
-                            let js_code = format!("set_editor_pos({}, {});", pos.x, pos.y + y_offs);
-                            js_sys::eval(&js_code);
+                                let js_code = format!("set_editor_pos({}, {});", pos.x, pos.y + y_offs);
+                                js_sys::eval(&js_code);
+                            }
                         });
                         // egui::ScrollArea::vertical()
                         //     .min_scrolled_height(screen_height() * 0.5)
                         //     .show(ui, |ui| {
                         //     });
 
-                        let mut code = code_editor.code.write().unwrap();
                         ui.vertical(|ui| {
                             ui.set_min_width(screen_width() * 0.1);
                             for (name, script) in crate::scripting::samples::SAMPLE_SCRIPTS {
                                 if ui.button(name).clicked() {
-                                    // *code = script.to_string();
+                                    #[cfg(not(target_arch = "wasm32"))]
+                                    {
+                                        *code = script.to_string();
+                                    }
+                                    #[cfg(target_arch = "wasm32")]
                                     js_sys::eval(&format!("set_editor_code(`{}`)", script.to_string())).unwrap();
                                 }
                             }
@@ -81,26 +263,25 @@ pub fn code_editor_sys(
             ui.add(egui::widgets::Separator::default().horizontal());
             ui.horizontal(|ui| {
                 if ui.button("Run").clicked() {
-                    code_editor.output = None;
-                    code_editor.should_run = true;
+                    source.output = None;
+                    source.should_run = true;
                 }
                 if ui.button("Clear Scene & Run").clicked() {
-                    code_editor.output = None;
-                    entities.iter().for_each(|e| commands.entity(e).despawn());
-                    rhai.existing_bodies.write().unwrap().clear();
-                    rhai.newly_added_bodies.write().unwrap().clear();
-                    code_editor.should_run = true;
+                    source.output = None;
+                    clear_scene(&entities, &mut commands, &rhai);
+                    source.should_run = true;
                 }
 
-                if code_editor.should_run {
-                    let mut code = code_editor.code.write().unwrap();
+                #[cfg(target_arch = "wasm32")]
+                if source.should_run {
+                    let mut code = source.code.write().unwrap();
                     let code_str = js_sys::eval("get_editor_code()").unwrap();
                     *code = code_str.as_string().unwrap();
                 }
 
                 #[cfg(target_arch = "wasm32")]
                 if ui.button("Download").clicked() {
-                    let code = code_editor.code.read().unwrap();
+                    let code = source.code.read().unwrap();
 
                     let js_code = format!(
                         "
@@ -126,7 +307,45 @@ pub fn code_editor_sys(
                 }
             });
 
-            if let Some(output) = code_editor.output.clone() {
+            egui::CollapsingHeader::new("Limits").default_open(false).show(ui, |ui| {
+                let mut limits = rhai.limits;
+                egui::Grid::new("script_limits").show(ui, |ui| {
+                    ui.label("Operations per call");
+                    ui.add(egui::DragValue::new(&mut limits.max_operations).speed(10_000.0));
+                    ui.end_row();
+
+                    ui.label("Call depth");
+                    ui.add(egui::DragValue::new(&mut limits.max_call_depth));
+                    ui.end_row();
+
+                    ui.label("Expression depth");
+                    ui.add(egui::DragValue::new(&mut limits.max_expr_depth));
+                    ui.end_row();
+
+                    ui.label("String length");
+                    ui.add(egui::DragValue::new(&mut limits.max_string_size).speed(1_000.0));
+                    ui.end_row();
+
+                    ui.label("Array/map size");
+                    ui.add(egui::DragValue::new(&mut limits.max_array_size).speed(1_000.0));
+                    ui.end_row();
+
+                    ui.label("Frame budget (ms)");
+                    ui.add(
+                        egui::DragValue::new(&mut limits.frame_budget_ms)
+                            .clamp_range(0.0..=10_000.0)
+                            .speed(1.0),
+                    );
+                    ui.end_row();
+                });
+                ui.label("0 turns a limit off");
+
+                if limits != rhai.limits {
+                    rhai.set_limits(limits);
+                }
+            });
+
+            if let Some(output) = source.output.clone() {
                 let output = output.read().unwrap();
                 let text =
                     RichText::new(format!("Output:\n{}", &output)).text_style(TextStyle::Monospace);
@@ -148,8 +367,11 @@ pub fn code_editor_sys(
             // });
         });
 
-    let js_code_shown = format!("set_editor_visibility({});", ace_shown);
-    js_sys::eval(&js_code_shown);
+    #[cfg(target_arch = "wasm32")]
+    {
+        let js_code_shown = format!("set_editor_visibility({});", ace_shown);
+        js_sys::eval(&js_code_shown);
+    }
 
     code_editor.shown = shown;
 }
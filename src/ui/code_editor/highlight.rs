@@ -0,0 +1,151 @@
+use egui_macroquad::egui::{
+    text::{LayoutJob, TextFormat},
+    Color32, FontId,
+};
+
+const KEYWORDS: [&str; 27] = [
+    "let", "const", "fn", "if", "else", "switch", "while", "loop", "do", "until", "for", "in",
+    "break", "continue", "return", "throw", "try", "catch", "import", "export", "as", "private",
+    "global", "this", "true", "false", "Fn",
+];
+
+const KEYWORD_COLOR: Color32 = Color32::from_rgb(198, 120, 221);
+const FUNCTION_COLOR: Color32 = Color32::from_rgb(97, 175, 239);
+const NUMBER_COLOR: Color32 = Color32::from_rgb(209, 154, 102);
+const STRING_COLOR: Color32 = Color32::from_rgb(152, 195, 121);
+const COMMENT_COLOR: Color32 = Color32::from_rgb(110, 118, 129);
+const TEXT_COLOR: Color32 = Color32::from_rgb(220, 223, 228);
+const ERROR_BACKGROUND: Color32 = Color32::from_rgb(90, 30, 30);
+
+#[derive(Clone, Copy)]
+enum Token {
+    Keyword,
+    Function,
+    Number,
+    String,
+    Comment,
+    Text,
+}
+
+impl Token {
+    fn color(self) -> Color32 {
+        match self {
+            Token::Keyword => KEYWORD_COLOR,
+            Token::Function => FUNCTION_COLOR,
+            Token::Number => NUMBER_COLOR,
+            Token::String => STRING_COLOR,
+            Token::Comment => COMMENT_COLOR,
+            Token::Text => TEXT_COLOR,
+        }
+    }
+}
+
+/// Splits one line into tokens. `in_comment` carries a `/* */` comment over
+/// from the previous line, and is left set if one is still open at the end.
+fn tokenize(line: &str, in_comment: &mut bool) -> Vec<(Token, usize, usize)> {
+    let bytes = line.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+
+        if *in_comment {
+            match line[i..].find("*/") {
+                Some(end) => {
+                    i += end + 2;
+                    *in_comment = false;
+                }
+                None => i = bytes.len(),
+            }
+            tokens.push((Token::Comment, start, i));
+            continue;
+        }
+
+        let c = bytes[i];
+        let token = if line[i..].starts_with("//") {
+            i = bytes.len();
+            Token::Comment
+        } else if line[i..].starts_with("/*") {
+            i += 2;
+            *in_comment = true;
+            Token::Comment
+        } else if c == b'"' || c == b'`' || c == b'\'' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != c {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            i = (i + 1).min(bytes.len());
+            Token::String
+        } else if c.is_ascii_digit() {
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'.')
+            {
+                i += 1;
+            }
+            Token::Number
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            if KEYWORDS.contains(&&line[start..i]) {
+                Token::Keyword
+            } else if line[i..].trim_start().starts_with('(') {
+                Token::Function
+            } else {
+                Token::Text
+            }
+        } else {
+            // advance by a whole character so that slicing stays on boundaries
+            i += line[i..].chars().next().map_or(1, char::len_utf8);
+            Token::Text
+        };
+
+        tokens.push((token, start, i));
+    }
+
+    tokens
+}
+
+/// Colours Rhai code, with a red background behind `error_line` (counted from 1)
+pub fn highlight(code: &str, font_id: FontId, error_line: Option<usize>) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    let mut in_comment = false;
+
+    for (i, line) in code.split_inclusive('\n').enumerate() {
+        let background = if error_line == Some(i + 1) {
+            ERROR_BACKGROUND
+        } else {
+            Color32::TRANSPARENT
+        };
+
+        for (token, start, end) in tokenize(line, &mut in_comment) {
+            job.append(
+                &line[start..end],
+                0.0,
+                TextFormat {
+                    font_id: font_id.clone(),
+                    color: token.color(),
+                    background,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    job
+}
+
+/// Finds the line of the last error in the script output. Rhai ends its
+/// errors with the position, like `(line 3, position 5)`.
+pub fn error_line(output: &str) -> Option<usize> {
+    output.rmatch_indices("line ").find_map(|(i, _)| {
+        let rest = &output[i + "line ".len()..];
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        if rest[digits..].starts_with(", position") {
+            rest[..digits].parse().ok()
+        } else {
+            None
+        }
+    })
+}
@@ -0,0 +1,83 @@
+use bevy_ecs::prelude::*;
+use egui_macroquad::egui::{self, Context};
+
+use crate::diagnostics::{relative_drift, DiagnosticsRes};
+
+fn drift_label(value: f32, reference: f32) -> String {
+    match relative_drift(value, reference) {
+        Some(drift) => format!("{:+.3e} (relative)", drift),
+        None => format!("{:+.3e}", value - reference),
+    }
+}
+
+pub fn diagnostics_panel_sys(egui_ctx: Res<Context>, mut diagnostics: ResMut<DiagnosticsRes>) {
+    let mut shown = diagnostics.shown;
+
+    egui::Window::new("Diagnostics")
+        .open(&mut shown)
+        .resizable(false)
+        .show(&egui_ctx, |ui| {
+            if let (Some(current), Some(reference)) = (diagnostics.current, diagnostics.reference) {
+                egui::Grid::new("diagnostics")
+                    .num_columns(3)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("");
+                        ui.label("Value");
+                        ui.label("Drift");
+                        ui.end_row();
+
+                        ui.label("Kinetic Energy");
+                        ui.label(format!("{:.4e}", current.kinetic_energy));
+                        ui.end_row();
+
+                        ui.label("Potential Energy");
+                        ui.label(format!("{:.4e}", current.potential_energy));
+                        ui.end_row();
+
+                        ui.label("Total Energy");
+                        ui.label(format!("{:.4e}", current.total_energy()));
+                        ui.label(drift_label(
+                            current.total_energy(),
+                            reference.total_energy(),
+                        ));
+                        ui.end_row();
+
+                        ui.label("Momentum");
+                        ui.label(format!(
+                            "({:.4e}, {:.4e})",
+                            current.momentum.x, current.momentum.y
+                        ));
+                        ui.label(format!(
+                            "{:+.3e}",
+                            (current.momentum - reference.momentum).length()
+                        ));
+                        ui.end_row();
+
+                        ui.label("Angular Momentum");
+                        ui.label(format!("{:.4e}", current.angular_momentum));
+                        ui.label(drift_label(
+                            current.angular_momentum,
+                            reference.angular_momentum,
+                        ));
+                        ui.end_row();
+
+                        ui.label("Center of Mass");
+                        ui.label(format!(
+                            "({:.2}, {:.2})",
+                            current.center_of_mass.x, current.center_of_mass.y
+                        ));
+                        ui.end_row();
+                    });
+            }
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut diagnostics.graph, "Graph");
+                if ui.button("Reset Drift").clicked() {
+                    diagnostics.reference = diagnostics.current;
+                }
+            });
+        });
+
+    diagnostics.shown = shown;
+}
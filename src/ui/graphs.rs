@@ -1,53 +1,118 @@
+use crate::scripting::graphs::{Graph, Graphs, PlotWindow};
 use crate::scripting::RhaiRes;
 use egui_macroquad::egui::{self, Context};
 use egui_macroquad::macroquad::prelude::*;
 use egui_plot::{Line, Plot, PlotPoints, Legend};
 use bevy_ecs::prelude::*;
 
-use std::collections::VecDeque;
-
-#[derive(Clone, Debug)]
-pub struct Graph {
-    pub points: VecDeque<f32>,
-    pub max_points: usize,
-    pub label: String,
-    pub color: [u8; 3],
+// log scales are drawn by plotting log10 of the values, so the axes and the
+// hover label have to undo it
+fn to_plot(value: f32, log: bool) -> Option<f64> {
+    match log {
+        true if value > 0.0 => Some((value as f64).log10()),
+        true => None,
+        false => Some(value as f64),
+    }
 }
 
-impl Graph {
-    pub fn new(name: &str, max_points: usize, r: u8, g: u8, b: u8) -> Graph {
-        Graph {
-            points: VecDeque::new(),
-            max_points,
-            label: name.to_string(),
-            color: [r, g, b],
-        }
+fn from_plot(value: f64, log: bool) -> f64 {
+    if log {
+        10f64.powf(value)
+    } else {
+        value
     }
 }
 
+fn format_log_mark(value: f64) -> String {
+    format!("{:.3e}", 10f64.powf(value))
+}
+
+fn csv_file_name(label: &str) -> String {
+    let name = label
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect::<String>();
+    format!("{}.csv", name)
+}
+
+fn export_csv(graph: &Graph, window: &PlotWindow) -> Result<String, String> {
+    let file_name = csv_file_name(&graph.label);
+    let csv = graph.to_csv(&window.x_label, &window.y_label);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    std::fs::write(&file_name, csv)
+        .map_err(|e| format!("Couldn't export {} to {}: {}", graph.label, file_name, e))?;
+    #[cfg(target_arch = "wasm32")]
+    crate::scene::call_js("download_text", &[&file_name, &csv]);
+
+    Ok(file_name)
+}
+
 pub fn draw_graphs_sys(
     rhai: Res<RhaiRes>,
     egui_ctx: Res<Context>,
 ) {
     let graphs_ref = rhai.graphs.clone();
-    let graphs = graphs_ref.read().unwrap();
-    if graphs.is_empty() {
+    let mut graphs = graphs_ref.write().unwrap();
+    if graphs.graphs.is_empty() {
         return;
     }
 
-    egui::Window::new("Graphs")
-        .default_pos(egui::Pos2::new(screen_width() * 0.75, 0.0))
-        .default_width(screen_width() * 0.2)
-        .default_height(screen_width() * 0.2)
-        .show(&egui_ctx, |ui| {
-            let legend = Legend::default();
-
-            Plot::new("Graphs")
-                .legend(legend)
-                .show_x(false)
-                .show(ui, |plot_ui| {
-                    for graph in graphs.values() {
-                        let points = graph.points.iter().enumerate().map(|(i, p)| [i as f64, *p as f64]);
+    let Graphs { graphs, windows, .. } = &mut *graphs;
+    let mut exports = Vec::new();
+
+    for (i, (window_name, window)) in windows.iter_mut().enumerate() {
+        let window_graphs = graphs
+            .values()
+            .filter(|graph| &graph.window == window_name)
+            .collect::<Vec<_>>();
+        if window_graphs.is_empty() {
+            continue;
+        }
+
+        egui::Window::new(window_name.as_str())
+            .id(egui::Id::new(("graph_window", window_name.as_str())))
+            .default_pos(egui::Pos2::new(screen_width() * 0.75, 30.0 * i as f32))
+            .default_width(screen_width() * 0.2)
+            .default_height(screen_width() * 0.2)
+            .show(&egui_ctx, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    ui.checkbox(&mut window.log_x, "Log x");
+                    ui.checkbox(&mut window.log_y, "Log y");
+                    ui.separator();
+                    for graph in &window_graphs {
+                        if ui.button(format!("Export {}", graph.label)).clicked() {
+                            exports.push(export_csv(graph, window));
+                        }
+                    }
+                });
+
+                let (log_x, log_y) = (window.log_x, window.log_y);
+                let legend = Legend::default();
+                let mut plot = Plot::new(window_name.as_str())
+                    .legend(legend)
+                    .x_axis_label(window.x_label.as_str())
+                    .y_axis_label(window.y_label.as_str())
+                    .label_formatter(move |name, point| {
+                        format!(
+                            "{}\nx = {:.4}\ny = {:.4}",
+                            name,
+                            from_plot(point.x, log_x),
+                            from_plot(point.y, log_y)
+                        )
+                    });
+                if log_x {
+                    plot = plot.x_axis_formatter(|value, _, _| format_log_mark(value));
+                }
+                if log_y {
+                    plot = plot.y_axis_formatter(|value, _, _| format_log_mark(value));
+                }
+
+                plot.show(ui, |plot_ui| {
+                    for graph in &window_graphs {
+                        let points = graph.points.iter().filter_map(|[x, y]| {
+                            Some([to_plot(*x, log_x)?, to_plot(*y, log_y)?])
+                        });
                         let plot_points = PlotPoints::new(points.collect());
 
                         let ecolor = egui::epaint::ecolor::Color32::from_rgb(graph.color[0], graph.color[1], graph.color[2]);
@@ -55,5 +120,14 @@ pub fn draw_graphs_sys(
                         plot_ui.line(line);
                     }
                 });
-        });
+            });
+    }
+
+    for export in exports {
+        let message = match export {
+            Ok(file_name) => format!("Exported {}\n", file_name),
+            Err(e) => format!("{}\n", e),
+        };
+        rhai.output.write().unwrap().push_str(&message);
+    }
 }
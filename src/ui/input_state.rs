@@ -1,7 +1,11 @@
 use bevy_ecs::prelude::*;
+use egui_macroquad::egui::Context;
 use egui_macroquad::macroquad::prelude::*;
 
 use crate::camera::CameraRes;
+use crate::scripting::{RhaiRes, ScriptEvent};
+
+use super::body_creation::CreationState;
 
 #[derive(Default)]
 pub struct MouseState {
@@ -11,3 +15,18 @@ pub struct MouseState {
 pub fn update_mouse_input_sys(mut mouse_state: ResMut<MouseState>, camera_res: Res<CameraRes>) {
     mouse_state.prev_position = camera_res.camera.screen_to_world(mouse_position().into());
 }
+
+/// Lets scripts react to clicks in the world that aren't creating a body
+pub fn script_click_sys(
+    mouse_state: Res<MouseState>,
+    creation_state: Res<CreationState>,
+    egui_ctx: Res<Context>,
+    rhai: Res<RhaiRes>,
+) {
+    if is_mouse_button_pressed(MouseButton::Left)
+        && *creation_state == CreationState::Unstarted
+        && !egui_ctx.is_pointer_over_area()
+    {
+        rhai.push_event(ScriptEvent::Click(mouse_state.prev_position));
+    }
+}
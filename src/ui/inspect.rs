@@ -3,15 +3,21 @@ use egui_macroquad::egui::{self, Context};
 use egui_macroquad::macroquad::prelude::*;
 use slotmap::Key;
 
-use crate::camera::FollowBody;
-use crate::physics::KinematicBody;
-use crate::scripting::RhaiID;
+use crate::orbit::{dominant_attractor, OrbitalElements};
+use crate::physics::{
+    BodyRestitution, BodySoftening, KinematicBody, Preview, Restitution, Softening, G,
+};
+use crate::preview::TrajectoryPrediction;
+use crate::scripting::{RhaiID, RhaiRes};
+use crate::selection::{FollowBody, InspectedEntity};
 use crate::trails::{RelativeTrails, Trail};
 
 use super::body_creation::CreationState;
 use super::input_state::MouseState;
 
-pub struct InspectedEntity(pub Option<Entity>);
+/// The body that orbital elements are measured from, or `None` to use
+/// whichever body pulls hardest on the inspected one
+pub struct OrbitPrimary(pub Option<Entity>);
 
 pub fn inspect_body_sys(
     mut inspected_entity: ResMut<InspectedEntity>,
@@ -44,14 +50,37 @@ pub fn inspect_panel_sys(
     rhai_ids: Query<&RhaiID>,
     mut followed_body: ResMut<FollowBody>,
     mut relative_trails_body: ResMut<RelativeTrails>,
-    mut body_info: Query<(&mut KinematicBody, &mut Trail)>,
+    mut query_set: QuerySet<(
+        Query<(
+            &mut KinematicBody,
+            &mut Trail,
+            Option<&mut BodySoftening>,
+            Option<&mut BodyRestitution>,
+            Option<&mut TrajectoryPrediction>,
+        )>,
+        Query<(Entity, &KinematicBody), Without<Preview>>,
+    )>,
+    softening: Res<Softening>,
+    restitution: Res<Restitution>,
+    g: Res<G>,
+    mut orbit_primary: ResMut<OrbitPrimary>,
+    rhai: Res<RhaiRes>,
     mut commands: Commands,
 ) {
     if let Some(entity) = inspected_entity.0 {
-        let (mut kinematic_body, mut trail) = match body_info.get_mut(entity) {
-            Ok(b) => b,
-            Err(_) => return,
-        };
+        let others = query_set
+            .q1()
+            .iter()
+            .filter(|(other, _)| *other != entity)
+            .map(|(other, body)| (other, body.clone()))
+            .collect::<Vec<_>>();
+
+        let body_info = query_set.q0_mut();
+        let (mut kinematic_body, mut trail, body_softening, body_restitution, prediction) =
+            match body_info.get_mut(entity) {
+                Ok(b) => b,
+                Err(_) => return,
+            };
 
         egui::SidePanel::left("Inspect").show(&egui_ctx, |ui| {
             ui.spacing_mut().slider_width = 200.0;
@@ -68,6 +97,34 @@ pub fn inspect_panel_sys(
                     .logarithmic(true),
             );
 
+            let mut custom_softening = body_softening.is_some();
+            if ui.checkbox(&mut custom_softening, "Custom Softening").changed() {
+                if custom_softening {
+                    commands.entity(entity).insert(BodySoftening(softening.0));
+                } else {
+                    commands.entity(entity).remove::<BodySoftening>();
+                }
+            }
+            if let Some(mut body_softening) = body_softening {
+                ui.add(
+                    egui::Slider::new(&mut body_softening.0, 0.0..=1_000.0)
+                        .text("Softening")
+                        .logarithmic(true),
+                );
+            }
+
+            let mut custom_restitution = body_restitution.is_some();
+            if ui.checkbox(&mut custom_restitution, "Custom Restitution").changed() {
+                if custom_restitution {
+                    commands.entity(entity).insert(BodyRestitution(restitution.0));
+                } else {
+                    commands.entity(entity).remove::<BodyRestitution>();
+                }
+            }
+            if let Some(mut body_restitution) = body_restitution {
+                ui.add(egui::Slider::new(&mut body_restitution.0, 0.0..=1.0).text("Restitution"));
+            }
+
             ui.label(format!(
                 "Position: <{:.2}, {:.2}>",
                 kinematic_body.pos.x, kinematic_body.pos.y
@@ -81,6 +138,33 @@ pub fn inspect_panel_sys(
                 kinematic_body.accel.x, kinematic_body.accel.y
             ));
 
+            egui::CollapsingHeader::new("Orbit")
+                .default_open(true)
+                .show(ui, |ui| {
+                    orbit_ui(
+                        ui,
+                        &mut kinematic_body,
+                        &others,
+                        &mut orbit_primary,
+                        &rhai_ids,
+                        g.0,
+                    );
+                });
+
+            let mut predict_path = prediction.is_some();
+            if ui.checkbox(&mut predict_path, "Predict Path").changed() {
+                if predict_path {
+                    commands
+                        .entity(entity)
+                        .insert(TrajectoryPrediction::default());
+                } else {
+                    commands.entity(entity).remove::<TrajectoryPrediction>();
+                }
+            }
+            if let Some(mut prediction) = prediction {
+                prediction_ui(ui, &mut prediction);
+            }
+
             ui.add(egui::Slider::new(&mut trail.max_len, 0..=10_000).text("Trail Max Length"));
 
             if let Ok(RhaiID(id)) = rhai_ids.get(entity) {
@@ -114,8 +198,184 @@ pub fn inspect_panel_sys(
                 }
             }
             if ui.button("Delete").clicked() {
-                commands.entity(entity).despawn();
+                let deleted = rhai_ids
+                    .get(entity)
+                    .is_ok_and(|RhaiID(id)| rhai.delete_body(*id));
+                if !deleted {
+                    commands.entity(entity).despawn();
+                }
             }
         });
     }
 }
+
+fn prediction_ui(ui: &mut egui::Ui, prediction: &mut TrajectoryPrediction) {
+    let horizon = ui.add(
+        egui::Slider::new(&mut prediction.horizon, 1.0..=100_000.0)
+            .text("Horizon")
+            .logarithmic(true),
+    );
+    let tick_interval = ui.add(
+        egui::Slider::new(&mut prediction.tick_interval, 1.0..=10_000.0)
+            .text("Tick Interval")
+            .logarithmic(true),
+    );
+    if horizon.changed() || tick_interval.changed() {
+        prediction.stale = true;
+    }
+
+    // big scenes run out of steps before reaching the horizon
+    if !prediction.stale
+        && prediction.impact.is_none()
+        && prediction.predicted_time < prediction.horizon * 0.999
+    {
+        ui.colored_label(
+            egui::Color32::YELLOW,
+            format!("Predicted to t+{:.0}", prediction.predicted_time),
+        )
+        .on_hover_text("The scene is too big to predict the whole horizon");
+    }
+}
+
+fn body_name(entity: Entity, rhai_ids: &Query<&RhaiID>) -> String {
+    match rhai_ids.get(entity) {
+        Ok(RhaiID(id)) => format!("id: {}", id.data().as_ffi()),
+        Err(_) => format!("{:?}", entity),
+    }
+}
+
+/// Shows the orbit of the inspected body around its primary, and moves the
+/// body when the elements are edited
+fn orbit_ui(
+    ui: &mut egui::Ui,
+    body: &mut KinematicBody,
+    others: &[(Entity, KinematicBody)],
+    orbit_primary: &mut OrbitPrimary,
+    rhai_ids: &Query<&RhaiID>,
+    g: f32,
+) {
+    let dominant = dominant_attractor(
+        body.pos,
+        others.iter().map(|(entity, body)| (*entity, body)),
+    );
+    let primary = orbit_primary
+        .0
+        .filter(|primary| others.iter().any(|(other, _)| other == primary))
+        .or(dominant);
+
+    let selected_text = match primary {
+        Some(primary) if orbit_primary.0 == Some(primary) => body_name(primary, rhai_ids),
+        Some(primary) => format!("Auto ({})", body_name(primary, rhai_ids)),
+        None => "Auto".to_string(),
+    };
+    egui::ComboBox::from_label("Primary")
+        .selected_text(selected_text)
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut orbit_primary.0, None, "Auto");
+            for (other, other_body) in others {
+                let text = format!(
+                    "{} (mass {:.1})",
+                    body_name(*other, rhai_ids),
+                    other_body.mass
+                );
+                ui.selectable_value(&mut orbit_primary.0, Some(*other), text);
+            }
+        });
+
+    let primary_body =
+        match primary.and_then(|primary| others.iter().find(|(other, _)| *other == primary)) {
+            Some((_, primary_body)) => primary_body,
+            None => {
+                ui.label("Nothing to orbit");
+                return;
+            }
+        };
+
+    let mu = g * (primary_body.mass + body.mass);
+    let rel_pos = body.pos - primary_body.pos;
+    let rel_vel = body.vel - primary_body.vel;
+    let mut elements = match OrbitalElements::from_state(rel_pos, rel_vel, mu) {
+        Some(elements) => elements,
+        None => {
+            ui.label("Not orbiting");
+            return;
+        }
+    };
+
+    let mut changed = false;
+    let mut argument_of_periapsis = elements.argument_of_periapsis.to_degrees();
+    let mut true_anomaly = elements.true_anomaly.to_degrees();
+
+    egui::Grid::new("orbital_elements").show(ui, |ui| {
+        ui.label("Semi-major axis");
+        let speed = (elements.semi_major_axis.abs() * 0.005).max(0.01);
+        changed |= ui
+            .add(egui::DragValue::new(&mut elements.semi_major_axis).speed(speed))
+            .changed();
+        ui.end_row();
+
+        ui.label("Eccentricity");
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut elements.eccentricity)
+                    .clamp_range(0.0..=10.0)
+                    .speed(0.001),
+            )
+            .changed();
+        ui.end_row();
+
+        ui.label("Arg. of periapsis");
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut argument_of_periapsis)
+                    .speed(0.5)
+                    .suffix("°"),
+            )
+            .changed();
+        ui.end_row();
+
+        ui.label("True anomaly");
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut true_anomaly)
+                    .speed(0.5)
+                    .suffix("°"),
+            )
+            .changed();
+        ui.end_row();
+
+        ui.label("Direction");
+        changed |= ui.checkbox(&mut elements.clockwise, "Clockwise").changed();
+        ui.end_row();
+
+        ui.label("Period");
+        match elements.period(mu) {
+            Some(period) => ui.label(format!("{:.2}", period)),
+            None => ui.label("unbound"),
+        };
+        ui.end_row();
+
+        ui.label("Periapsis");
+        ui.label(format!("{:.2}", elements.periapsis()));
+        ui.end_row();
+
+        ui.label("Apoapsis");
+        match elements.apoapsis() {
+            Some(apoapsis) => ui.label(format!("{:.2}", apoapsis)),
+            None => ui.label("unbound"),
+        };
+        ui.end_row();
+    });
+
+    if changed {
+        elements.argument_of_periapsis = argument_of_periapsis.to_radians();
+        elements.true_anomaly = true_anomaly.to_radians();
+
+        // edits that don't make an orbit through the body, like a hyperbola
+        // past its asymptotes, are ignored
+        if let Some((pos, vel)) = elements.to_state(mu) {
+            body.pos = primary_body.pos + pos;
+            body.vel = primary_body.vel + vel;
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use bevy_ecs::prelude::*;
+use egui_macroquad::egui::{self, Context};
+use egui_macroquad::macroquad::prelude::*;
+
+use crate::scripting::controls::{ScriptControl, ScriptControls};
+use crate::scripting::RhaiRes;
+
+pub fn script_controls_sys(rhai: Res<RhaiRes>, egui_ctx: Res<Context>) {
+    let controls_ref = rhai.controls.clone();
+    let mut script_controls = controls_ref.write().unwrap();
+    if script_controls.controls.is_empty() {
+        return;
+    }
+
+    let ScriptControls {
+        controls, pressed, ..
+    } = &mut *script_controls;
+
+    egui::Window::new("Script Controls")
+        .default_pos(egui::Pos2::new(
+            screen_width() * 0.75,
+            screen_height() * 0.5,
+        ))
+        .resizable(false)
+        .show(&egui_ctx, |ui| {
+            for (name, control) in controls.iter_mut() {
+                match control {
+                    ScriptControl::Slider { value, min, max } => {
+                        ui.add(egui::Slider::new(value, *min..=*max).text(name.as_str()));
+                    }
+                    ScriptControl::Checkbox(checked) => {
+                        ui.checkbox(checked, name.as_str());
+                    }
+                    ScriptControl::Button(_) => {
+                        if ui.button(name.as_str()).clicked() {
+                            pressed.push(name.clone());
+                        }
+                    }
+                }
+            }
+        });
+}
@@ -3,15 +3,21 @@ use egui::menu;
 use egui_macroquad::egui;
 
 use crate::{
+    diagnostics::DiagnosticsRes,
     force_lines::DrawForceLines,
-    physics::{Paused, DT, G},
+    history::History,
+    physics::{
+        AdaptiveDT, CollisionMode, ExternalForces, Fragmentation, GravitySolver, Integrator,
+        Paused, Restitution, Softening, Steps, DT, G,
+    },
     preview::MultiPreview,
-    scripting::RhaiRes,
+    scene::{SceneAction, SceneIO},
+    scripting::{RhaiID, RhaiRes},
     trails::{DrawTrails, RelativeTrails}, camera::CameraRes,
 };
 
 use super::{
-    body_creation::{CreationData, CreationState},
+    body_creation::{CreationData, CreationMode, CreationState},
     code_editor::CodeEditor,
 };
 
@@ -27,13 +33,64 @@ pub fn top_panel_sys(
     mut _multi_preview: ResMut<MultiPreview>,
     mut g: ResMut<G>,
     mut dt: ResMut<DT>,
+    (
+        mut integrator,
+        mut gravity_solver,
+        mut adaptive_dt,
+        mut steps,
+        mut softening,
+        mut collision_mode,
+        mut restitution,
+        mut fragmentation,
+        mut diagnostics,
+        mut history,
+        rhai,
+        external_forces,
+    ): (
+        ResMut<Integrator>,
+        ResMut<GravitySolver>,
+        ResMut<AdaptiveDT>,
+        ResMut<Steps>,
+        ResMut<Softening>,
+        ResMut<CollisionMode>,
+        ResMut<Restitution>,
+        ResMut<Fragmentation>,
+        ResMut<DiagnosticsRes>,
+        ResMut<History>,
+        Res<RhaiRes>,
+        Res<ExternalForces>,
+    ),
     mut camera: ResMut<CameraRes>,
-    entities: Query<Entity>,
-    _rhai: Res<RhaiRes>,
+    entities: Query<(Entity, Option<&RhaiID>)>,
+    mut scene_io: ResMut<SceneIO>,
     mut commands: Commands,
 ) {
     egui::TopBottomPanel::top("SIMple Gravity").show(&egui_ctx, |ui| {
         menu::bar(ui, |ui| {
+            menu::menu_button(ui, "Scene", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    ui.text_edit_singleline(&mut scene_io.path);
+                });
+
+                if ui.button("Save").clicked() {
+                    let path = scene_io.path.clone();
+                    scene_io.pending.push(SceneAction::Save(path));
+                    ui.close_menu();
+                }
+
+                if ui.button("Open").clicked() {
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        let path = scene_io.path.clone();
+                        scene_io.pending.push(SceneAction::Load(path));
+                    }
+                    #[cfg(target_arch = "wasm32")]
+                    scene_io.pending.push(SceneAction::Open);
+                    ui.close_menu();
+                }
+            });
+
             menu::menu_button(ui, "Create", |ui| {
                 ui.spacing_mut().slider_width = 500.0;
                 ui.add(
@@ -47,6 +104,19 @@ pub fn top_panel_sys(
                         .logarithmic(true),
                 );
 
+                ui.menu_button(format!("Velocity: {}", creation_data.mode.label()), |ui| {
+                    for option in CreationMode::ALL {
+                        ui.radio_value(&mut creation_data.mode, option, option.label());
+                    }
+                });
+                if creation_data.mode != CreationMode::Drag {
+                    ui.add(
+                        egui::Slider::new(&mut creation_data.eccentricity, 0.0..=2.0)
+                            .text("Eccentricity"),
+                    );
+                    ui.checkbox(&mut creation_data.clockwise, "Clockwise");
+                }
+
                 if ui.button("Create").clicked() {
                     *creation_state = CreationState::Initiated;
                 }
@@ -57,8 +127,92 @@ pub fn top_panel_sys(
                 ui.checkbox(&mut draw_force_lines.0, "Draw Force Lines");
                 ui.checkbox(&mut draw_trails.0, "Draw Trails");
                 // ui.checkbox(&mut multi_preview.0, "Multi Preview");
-                ui.add(egui::Slider::new(&mut dt.0, 0.0..=10.0).text("Timestep"));
+                ui.checkbox(&mut adaptive_dt.enabled, "Adaptive Timestep");
+                if adaptive_dt.enabled {
+                    ui.add(egui::Slider::new(&mut adaptive_dt.min, 0.0..=10.0).text("Min Timestep"));
+                    ui.add(egui::Slider::new(&mut adaptive_dt.max, 0.0..=10.0).text("Max Timestep"));
+                    ui.add(
+                        egui::Slider::new(&mut adaptive_dt.tolerance, 0.001..=1.0)
+                            .text("Tolerance")
+                            .logarithmic(true),
+                    );
+                } else {
+                    ui.add(egui::Slider::new(&mut dt.0, 0.0..=10.0).text("Timestep"));
+                }
                 ui.add(egui::Slider::new(&mut g.0, 0.0..=1000.0).text("G"));
+                ui.add(
+                    egui::Slider::new(&mut softening.0, 0.0..=1000.0)
+                        .text("Softening")
+                        .logarithmic(true),
+                );
+
+                let effective = integrator.effective(external_forces.0);
+                let integrator_text = if effective == *integrator {
+                    format!("Integrator: {}", integrator.label())
+                } else {
+                    format!(
+                        "Integrator: {} (using {})",
+                        integrator.label(),
+                        effective.label()
+                    )
+                };
+                let integrator_menu = ui.menu_button(integrator_text, |ui| {
+                    for option in Integrator::ALL {
+                        ui.radio_value(&mut *integrator, option, option.label());
+                    }
+                });
+                if effective != *integrator {
+                    integrator_menu.response.on_hover_text(
+                        "Forces added by scripts are integrated with Velocity Verlet",
+                    );
+                }
+
+                ui.menu_button(format!("Collisions: {}", collision_mode.label()), |ui| {
+                    for option in CollisionMode::ALL {
+                        ui.radio_value(&mut *collision_mode, option, option.label());
+                    }
+                });
+                if *collision_mode == CollisionMode::Inelastic {
+                    ui.add(egui::Slider::new(&mut restitution.0, 0.0..=1.0).text("Restitution"));
+                }
+                if *collision_mode == CollisionMode::Merge {
+                    ui.checkbox(&mut fragmentation.enabled, "Fragmentation");
+                }
+                if *collision_mode == CollisionMode::Merge && fragmentation.enabled {
+                    ui.add(
+                        egui::Slider::new(&mut fragmentation.threshold, 0.01..=100.0)
+                            .text("Fragmentation Threshold")
+                            .logarithmic(true),
+                    );
+                    ui.add(egui::Slider::new(&mut fragmentation.fragments, 2..=16).text("Fragments"));
+                    ui.add(
+                        egui::Slider::new(&mut fragmentation.min_mass, 0.0..=100.0)
+                            .text("Min Fragment Mass")
+                            .logarithmic(true),
+                    );
+                }
+
+                ui.checkbox(&mut gravity_solver.barnes_hut, "Barnes-Hut Gravity");
+                if gravity_solver.barnes_hut {
+                    ui.add(egui::Slider::new(&mut gravity_solver.theta, 0.0..=2.0).text("Opening Angle θ"));
+                }
+
+                ui.checkbox(&mut history.enabled, "Timeline");
+                if history.enabled {
+                    ui.add(
+                        egui::Slider::new(&mut history.interval, 1..=1000)
+                            .text("Steps per Snapshot")
+                            .logarithmic(true),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut history.capacity, 10..=1000)
+                            .text("Max Snapshots")
+                            .logarithmic(true),
+                    );
+                    if ui.button("Clear Timeline").clicked() {
+                        history.clear();
+                    }
+                }
 
                 if ui.button("Stop Relative Trails").clicked() {
                     *relative_trails_body = RelativeTrails(None);
@@ -75,15 +229,80 @@ pub fn top_panel_sys(
                 paused.0 = !paused.0;
             }
 
+            menu::menu_button(ui, format!("Speed ×{}", steps.0), |ui| {
+                ui.spacing_mut().slider_width = 300.0;
+                ui.add(
+                    egui::Slider::new(&mut steps.0, 1..=1000)
+                        .text("Steps per Frame")
+                        .logarithmic(true),
+                );
+            });
+
+            if adaptive_dt.enabled {
+                ui.label(format!("dt: {:.4}", dt.0));
+            }
+
+            if history.enabled && !history.snapshots.is_empty() {
+                timeline_ui(ui, &mut history, &mut paused);
+            }
+
+            ui.toggle_value(&mut diagnostics.shown, "Diagnostics");
+
             if ui.button("Reset Camera").clicked() {
                 *camera = CameraRes::default();
             }
 
             if ui.button("Clear Scene").clicked() {
-                for entity in entities.iter() {
-                    commands.entity(entity).despawn();
+                for (entity, id) in entities.iter() {
+                    if !id.is_some_and(|RhaiID(id)| rhai.delete_body(*id)) {
+                        commands.entity(entity).despawn();
+                    }
                 }
             }
         });
     });
 }
+
+/// Scrubbing pauses at a snapshot, and unpausing continues from it
+fn timeline_ui(ui: &mut egui::Ui, history: &mut History, paused: &mut Paused) {
+    let len = history.snapshots.len();
+    // while running, one past the last snapshot stands for the live state
+    let current = history.cursor.unwrap_or(len);
+    let last = if history.cursor.is_some() { len - 1 } else { len };
+
+    if ui
+        .add_enabled(current > 0, egui::Button::new("◀"))
+        .clicked()
+    {
+        history.pending = Some(current - 1);
+    }
+
+    let mut index = current;
+    ui.add(egui::Slider::new(&mut index, 0..=last).show_value(false));
+    if index != current && index < len {
+        history.pending = Some(index);
+    }
+
+    if ui
+        .add_enabled(current + 1 < len, egui::Button::new("▶"))
+        .clicked()
+    {
+        history.pending = Some(current + 1);
+    }
+
+    if let Some(snapshot) = history.cursor.and_then(|cursor| history.snapshots.get(cursor)) {
+        ui.label(format!("t = {:.2}", snapshot.time()));
+        if ui
+            .button("Branch")
+            .on_hover_text("Continue from here, dropping the later snapshots")
+            .clicked()
+        {
+            paused.0 = false;
+        }
+    }
+
+    if history.script_restarted {
+        ui.colored_label(egui::Color32::YELLOW, "Script restarted")
+            .on_hover_text("Script variables can't be rewound, so the script was run again from the start");
+    }
+}